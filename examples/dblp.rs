@@ -1,13 +1,14 @@
-/*
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 #![allow(dead_code)]
 
+//! Expects the DBLP arrays in `datasets/dblp.npz`; see `candle_gnn::datasets::Dblp`.
+
 use candle_core::{DType, Device, IndexOp, Module, Result, Tensor, D};
 use candle_nn::{linear, loss, ops, Init, Linear, Optimizer, VarBuilder, VarMap};
 
 use candle_gnn::datasets::{Dblp, EdgeType, NodeType};
-use candle_gnn::nn::{hetero_gcn, HeteroGcnConv, HeteroGnnModule, utils::apply};
+use candle_gnn::nn::{hetero_gcn, utils::apply, HeteroGcnConv, HeteroGnnModule};
 use candle_gnn::utils::mask_to_index;
 
 use EdgeType::*;
@@ -114,6 +115,3 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
-
- */
- fn main() { }
\ No newline at end of file
@@ -1,16 +1,20 @@
 use anyhow::Result;
 use candle_core::{DType, Device, IndexOp, D};
 use candle_gnn::datasets::{
-    CiteSeerDataset, CoraDataset, FullBatchLoader, PubMedDiabetesDataset, RandomSplit,
+    CachedLoader, CiteSeerDataset, CoraDataset, FullBatchLoader, NodeSplit, PubMedDiabetesDataset,
 };
-use candle_gnn::nn::{Gcn, GnnModule};
+use candle_gnn::nn::{Gcn, GcnParams, GnnModule};
 use candle_nn::loss::cross_entropy;
 use candle_nn::{AdamW, Optimizer, ParamsAdamW};
 
 fn cora() -> Result<()> {
     let device = Device::cuda_if_available(0)?;
     let dataset = CoraDataset::new("datasets/cora")?;
-    let [train_dataset, test_dataset] = dataset.random_split([0.8, 0.2])?;
+    // the public split sizes of Planetoid (https://arxiv.org/abs/1603.08861)
+    let [train_dataset, _, test_dataset] = dataset.per_class_split(20, 500, 1000, None)?;
+    // the batches do not change between epochs, so they are built once
+    let train_loader = CachedLoader::new(FullBatchLoader::new(&train_dataset, &device))?;
+    let test_loader = CachedLoader::new(FullBatchLoader::new(&test_dataset, &device))?;
 
     let model = Gcn::new(
         &[dataset.num_features(), 16, dataset.num_classes()],
@@ -22,7 +26,7 @@ fn cora() -> Result<()> {
         // training
         let mut train_loss = 0.0;
         let mut train_accuracy = 0.0;
-        for batch in FullBatchLoader::new(&train_dataset, &device) {
+        for batch in &train_loader {
             let logits = model.forward_t(&batch.xs, &batch.edge_index, true)?;
             let loss = cross_entropy(&logits.i(&batch.mask)?, &batch.ys)?;
             optimizer.backward_step(&loss)?;
@@ -39,7 +43,7 @@ fn cora() -> Result<()> {
 
         // validation
         if epoch % 10 == 0 {
-            for batch in FullBatchLoader::new(&test_dataset, &device) {
+            for batch in &test_loader {
                 let logits = model.forward(&batch.xs, &batch.edge_index)?;
                 let accuracy = logits
                     .i(&batch.mask)?
@@ -63,7 +67,9 @@ fn cora() -> Result<()> {
 fn citeseer() -> Result<()> {
     let device = Device::cuda_if_available(0)?;
     let dataset = CiteSeerDataset::new("datasets/citeseer")?;
-    let [train_dataset, test_dataset] = dataset.random_split([0.8, 0.2])?;
+    let [train_dataset, _, test_dataset] = dataset.per_class_split(20, 500, 1000, None)?;
+    let train_loader = CachedLoader::new(FullBatchLoader::new(&train_dataset, &device))?;
+    let test_loader = CachedLoader::new(FullBatchLoader::new(&test_dataset, &device))?;
 
     let model = Gcn::new(
         &[dataset.num_features(), 16, dataset.num_classes()],
@@ -75,7 +81,7 @@ fn citeseer() -> Result<()> {
         // training
         let mut train_loss = 0.0;
         let mut train_accuracy = 0.0;
-        for batch in FullBatchLoader::new(&train_dataset, &device) {
+        for batch in &train_loader {
             let logits = model.forward_t(&batch.xs, &batch.edge_index, true)?;
             let loss = cross_entropy(&logits.i(&batch.mask)?, &batch.ys)?;
             optimizer.backward_step(&loss)?;
@@ -92,7 +98,7 @@ fn citeseer() -> Result<()> {
 
         // validation
         if epoch % 10 == 0 {
-            for batch in FullBatchLoader::new(&test_dataset, &device) {
+            for batch in &test_loader {
                 let logits = model.forward(&batch.xs, &batch.edge_index)?;
                 let accuracy = logits
                     .i(&batch.mask)?
@@ -116,10 +122,17 @@ fn citeseer() -> Result<()> {
 fn pubmed_diabetes() -> Result<()> {
     let device = Device::cuda_if_available(0)?;
     let dataset = PubMedDiabetesDataset::new("datasets/pubmed_diabetes")?;
-    let [train_dataset, test_dataset] = dataset.random_split([0.8, 0.2])?;
+    let [train_dataset, _, test_dataset] = dataset.per_class_split(20, 500, 1000, None)?;
+    let train_loader = CachedLoader::new(FullBatchLoader::new(&train_dataset, &device))?;
+    let test_loader = CachedLoader::new(FullBatchLoader::new(&test_dataset, &device))?;
 
-    let model = Gcn::new(
+    // the splits share the graph, so the normalization is computed only once
+    let model = Gcn::with_params(
         &[dataset.num_features(), 64, dataset.num_classes()],
+        GcnParams {
+            cached: true,
+            ..Default::default()
+        },
         &device,
     )?;
     let mut optimizer = AdamW::new(model.parameters(), ParamsAdamW::default())?;
@@ -128,7 +141,7 @@ fn pubmed_diabetes() -> Result<()> {
         // training
         let mut train_loss = 0.0;
         let mut train_accuracy = 0.0;
-        for batch in FullBatchLoader::new(&train_dataset, &device) {
+        for batch in &train_loader {
             let logits = model.forward_t(&batch.xs, &batch.edge_index, true)?;
             let loss = cross_entropy(&logits.i(&batch.mask)?, &batch.ys)?;
             optimizer.backward_step(&loss)?;
@@ -145,7 +158,7 @@ fn pubmed_diabetes() -> Result<()> {
 
         // validation
         if epoch % 10 == 0 {
-            for batch in FullBatchLoader::new(&test_dataset, &device) {
+            for batch in &test_loader {
                 let logits = model.forward(&batch.xs, &batch.edge_index)?;
                 let accuracy = logits
                     .i(&batch.mask)?
@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+/// Batches of a loader computed once and kept on their device, so that the epochs after
+/// the first do not redo the joins and the copies.
+///
+/// Only deterministic loaders such as `FullBatchLoader` should be cached; the sampling
+/// loaders would yield the same samples every epoch.
+#[derive(Debug, Clone)]
+pub struct CachedLoader<B> {
+    batches: Vec<B>,
+}
+impl<B> CachedLoader<B> {
+    /// Runs `loader` to the end, failing on its first error.
+    pub fn new<I: IntoIterator<Item = Result<B>>>(loader: I) -> Result<Self> {
+        let batches = loader.into_iter().collect::<Result<_>>()?;
+        Ok(Self { batches })
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, B> {
+        self.batches.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+}
+impl<'a, B> IntoIterator for &'a CachedLoader<B> {
+    type Item = &'a B;
+    type IntoIter = std::slice::Iter<'a, B>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
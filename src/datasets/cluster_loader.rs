@@ -0,0 +1,346 @@
+use std::{
+    fs::{create_dir_all, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use candle_core::Device;
+use polars::{
+    frame::DataFrame,
+    io::{
+        parquet::{ParquetReader, ParquetWriter},
+        SerReader,
+    },
+    prelude::{df, NamedFrom},
+};
+
+use super::sampling::{subgraph, Adjacency, Rng};
+use super::traits::{Dataset, PolarsDataset};
+
+#[derive(Debug, Clone)]
+pub struct ClusterLoaderParams {
+    pub num_parts: usize,
+    pub clusters_per_batch: usize,
+    pub shuffle: bool,
+    pub seed: Option<u64>,
+    /// Rounds of label propagation refining the initial partition.
+    pub num_iters: usize,
+    /// Root of the dataset; the partition is cached in `root/partition/`, so that it is
+    /// computed only once per `num_parts`, `num_iters` and `seed`. Without a seed, the
+    /// first random partition is reused.
+    pub root: Option<PathBuf>,
+}
+impl Default for ClusterLoaderParams {
+    fn default() -> Self {
+        Self {
+            num_parts: 100,
+            clusters_per_batch: 10,
+            shuffle: true,
+            seed: None,
+            num_iters: 10,
+            root: None,
+        }
+    }
+}
+
+/// Mini-batch loader over a partition of the graph into clusters
+/// (https://arxiv.org/abs/1905.07953).
+///
+/// Each batch is the subgraph induced by `clusters_per_batch` random clusters, edges
+/// between them included. The masked nodes of the dataset keep their mask, so the loss
+/// is computed as with `FullBatchLoader`.
+///
+/// The partition is balanced label propagation, initialized with contiguous chunks of
+/// a breadth-first order of the nodes.
+pub struct ClusterLoader<'a, D> {
+    dataset: &'a D,
+    device: &'a Device,
+    partition: Vec<u32>,
+    clusters: Vec<Vec<u32>>,
+    mask: Vec<bool>,
+    params: ClusterLoaderParams,
+    rng: Rng,
+}
+impl<'a, D> ClusterLoader<'a, D>
+where
+    D: Dataset<NodeSelector = DataFrame> + PolarsDataset,
+{
+    pub fn new(dataset: &'a D, params: ClusterLoaderParams, device: &'a Device) -> Result<Self> {
+        if params.num_parts == 0 || params.clusters_per_batch == 0 {
+            anyhow::bail!("number of parts and clusters per batch must be positive")
+        }
+        let mut rng = Rng::new(params.seed);
+        let num_nodes = dataset.node_df().height();
+        let path = params.root.as_ref().map(|root| {
+            let seed = params
+                .seed
+                .map_or("none".to_string(), |seed| seed.to_string());
+            root.join("partition").join(format!(
+                "parts_{}_iters_{}_seed_{}.parquet",
+                params.num_parts, params.num_iters, seed
+            ))
+        });
+        let cached = match &path {
+            Some(path) => read_partition(path, num_nodes)?,
+            None => None,
+        };
+        let partition = match cached {
+            Some(partition) => partition,
+            None => {
+                let adjacency = Adjacency::new(dataset)?;
+                let partition =
+                    label_propagation(&adjacency, params.num_parts, params.num_iters, &mut rng);
+                if let Some(path) = &path {
+                    create_dir_all(path.parent().unwrap())?;
+                    let mut df = df! { "part" => &partition }?;
+                    ParquetWriter::new(File::create(path)?).finish(&mut df)?;
+                }
+                partition
+            }
+        };
+
+        let mut clusters = vec![Vec::new(); params.num_parts];
+        for (i, &p) in partition.iter().enumerate() {
+            clusters[p as usize].push(i as u32);
+        }
+        clusters.retain(|cluster| !cluster.is_empty());
+        let mask = dataset.node_df()["mask"]
+            .bool()?
+            .into_no_null_iter()
+            .collect();
+        Ok(Self {
+            dataset,
+            device,
+            partition,
+            clusters,
+            mask,
+            params,
+            rng,
+        })
+    }
+
+    /// Cluster of each node, in the order of the node table.
+    pub fn partition(&self) -> &[u32] {
+        &self.partition
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.clusters.len().div_ceil(self.params.clusters_per_batch)
+    }
+
+    /// Batches of one epoch, regrouping the clusters if `shuffle` is set.
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<D::Batch>> + use<'_, 'a, D> {
+        let mut order: Vec<usize> = (0..self.clusters.len()).collect();
+        if self.params.shuffle {
+            self.rng.shuffle(&mut order);
+        }
+        let clusters_per_batch = self.params.clusters_per_batch;
+        (0..order.len())
+            .step_by(clusters_per_batch)
+            .map(move |start| {
+                let end = (start + clusters_per_batch).min(order.len());
+                let nodes: Vec<u32> = order[start..end]
+                    .iter()
+                    .flat_map(|&c| self.clusters[c].iter().copied())
+                    .collect();
+                let seeds: Vec<u32> = nodes
+                    .iter()
+                    .copied()
+                    .filter(|&i| self.mask[i as usize])
+                    .collect();
+                subgraph(self.dataset, &nodes, &seeds, self.device)
+            })
+    }
+}
+
+// `Ok(None)` if there is no cache or it was computed for another graph
+fn read_partition(path: &Path, num_nodes: usize) -> Result<Option<Vec<u32>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let df = ParquetReader::new(File::open(path)?).finish()?;
+    let partition: Vec<u32> = df["part"].u32()?.into_no_null_iter().collect();
+    Ok((partition.len() == num_nodes).then_some(partition))
+}
+
+fn label_propagation(
+    adjacency: &Adjacency,
+    num_parts: usize,
+    num_iters: usize,
+    rng: &mut Rng,
+) -> Vec<u32> {
+    let num_nodes = adjacency.num_nodes();
+    let num_parts = num_parts.min(num_nodes.max(1));
+
+    // breadth-first order from random roots, so that the chunks are mostly connected
+    let mut order = Vec::with_capacity(num_nodes);
+    let mut visited = vec![false; num_nodes];
+    let mut roots: Vec<u32> = (0..num_nodes as u32).collect();
+    rng.shuffle(&mut roots);
+    for root in roots {
+        if visited[root as usize] {
+            continue;
+        }
+        visited[root as usize] = true;
+        let mut head = order.len();
+        order.push(root);
+        while head < order.len() {
+            let i = order[head];
+            head += 1;
+            for &j in adjacency.neighbors(i) {
+                if !visited[j as usize] {
+                    visited[j as usize] = true;
+                    order.push(j);
+                }
+            }
+        }
+    }
+    let mut parts = vec![0; num_nodes];
+    let mut sizes = vec![0; num_parts];
+    for (rank, &i) in order.iter().enumerate() {
+        let p = rank * num_parts / num_nodes;
+        parts[i as usize] = p;
+        sizes[p] += 1;
+    }
+
+    // move each node to the most frequent part among its neighbors, allowing 10%
+    // imbalance
+    let capacity = num_nodes.div_ceil(num_parts) * 11 / 10;
+    let mut counts = vec![0; num_parts];
+    for _ in 0..num_iters {
+        rng.shuffle(&mut order);
+        let mut moved = 0;
+        for &i in &order {
+            let neighbors = adjacency.neighbors(i);
+            let p = parts[i as usize];
+            for &j in neighbors {
+                counts[parts[j as usize]] += 1;
+            }
+            let mut best = p;
+            if neighbors.is_empty() {
+                // isolated nodes only balance the parts
+                let q = (0..num_parts).min_by_key(|&q| sizes[q]).unwrap();
+                if sizes[q] + 1 < sizes[p] {
+                    best = q;
+                }
+            }
+            for &j in neighbors {
+                let q = parts[j as usize];
+                if counts[q] > counts[best] && sizes[q] < capacity {
+                    best = q;
+                }
+            }
+            for &j in neighbors {
+                counts[parts[j as usize]] = 0;
+            }
+            if best != p && sizes[p] > 1 {
+                sizes[p] -= 1;
+                sizes[best] += 1;
+                parts[i as usize] = best;
+                moved += 1;
+            }
+        }
+        if moved == 0 {
+            break;
+        }
+    }
+    parts.into_iter().map(|p| p as u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::datasets::sampling::tests::ToyDataset;
+
+    // two 4-cliques joined by the edge 3 - 4, and the isolated node 8
+    fn toy_edges() -> Vec<(u32, u32)> {
+        let mut edges = vec![(3, 4), (4, 3)];
+        for offset in [0, 4] {
+            for i in 0..4 {
+                for j in 0..4 {
+                    if i != j {
+                        edges.push((offset + i, offset + j));
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn test_label_propagation() {
+        let (rows, cols): (Vec<u32>, Vec<u32>) = toy_edges().into_iter().unzip();
+        let adjacency = Adjacency::from_edges(9, &rows, &cols);
+        for num_parts in [1, 2, 3, 9, 20] {
+            let partition = label_propagation(&adjacency, num_parts, 10, &mut Rng::new(Some(0)));
+            assert_eq!(partition.len(), 9);
+            let num_parts = num_parts.min(9);
+            let mut sizes = vec![0; num_parts];
+            for p in partition {
+                assert!((p as usize) < num_parts);
+                sizes[p as usize] += 1;
+            }
+            let capacity = 9usize.div_ceil(num_parts) * 11 / 10;
+            assert!(sizes.iter().all(|&size| size <= capacity), "{:?}", sizes);
+        }
+    }
+
+    #[test]
+    fn test_cluster_loader() -> Result<()> {
+        let device = Device::Cpu;
+        let dataset = ToyDataset::new(&[0; 9], &toy_edges())?;
+        let params = ClusterLoaderParams {
+            num_parts: 3,
+            clusters_per_batch: 2,
+            seed: Some(0),
+            ..Default::default()
+        };
+        let mut loader = ClusterLoader::new(&dataset, params, &device)?;
+        assert_eq!(loader.num_batches(), 2);
+        let partition = loader.partition().to_vec();
+        // every node is in exactly one batch of an epoch, with all of its cluster
+        let mut counts = [0; 9];
+        for batch in loader.iter() {
+            let batch = batch?;
+            for &i in &batch.nodes {
+                counts[i as usize] += 1;
+            }
+            let parts: HashSet<u32> = batch.nodes.iter().map(|&i| partition[i as usize]).collect();
+            let size = partition.iter().filter(|p| parts.contains(p)).count();
+            assert_eq!(batch.nodes.len(), size);
+            assert_eq!(batch.mask.len(), size);
+        }
+        assert_eq!(counts, [1; 9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_loader_cache() -> Result<()> {
+        let device = Device::Cpu;
+        let root = tempfile::tempdir()?;
+        let dataset = ToyDataset::new(&[0; 9], &toy_edges())?;
+        let params = ClusterLoaderParams {
+            num_parts: 3,
+            seed: Some(0),
+            root: Some(root.path().to_path_buf()),
+            ..Default::default()
+        };
+        let partition = ClusterLoader::new(&dataset, params.clone(), &device)?
+            .partition()
+            .to_vec();
+        let path = root
+            .path()
+            .join("partition/parts_3_iters_10_seed_0.parquet");
+        assert!(path.exists());
+        let cached = ClusterLoader::new(&dataset, params.clone(), &device)?;
+        assert_eq!(cached.partition(), partition);
+
+        // a corrupt cache is reported, not overwritten
+        std::fs::write(&path, "corrupt")?;
+        assert!(ClusterLoader::new(&dataset, params, &device).is_err());
+        assert_eq!(std::fs::read(&path)?, b"corrupt");
+        Ok(())
+    }
+}
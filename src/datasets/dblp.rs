@@ -0,0 +1,283 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+
+use super::io::npz::{NpzReader, NpzWriter};
+use crate::{utils::mask_to_index, HeteroData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum NodeType {
+    Author,
+    Paper,
+    Term,
+    Conference,
+}
+impl NodeType {
+    pub const ALL: [NodeType; 4] = [
+        NodeType::Author,
+        NodeType::Paper,
+        NodeType::Term,
+        NodeType::Conference,
+    ];
+}
+impl Display for NodeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NodeType::Author => "author",
+            NodeType::Paper => "paper",
+            NodeType::Term => "term",
+            NodeType::Conference => "conference",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeType {
+    To,
+}
+impl Display for EdgeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdgeType::To => write!(f, "to"),
+        }
+    }
+}
+
+/// DBLP academic graph (https://arxiv.org/abs/2002.01680), authors labelled by
+/// research area.
+///
+/// `from_file` reads a local `.npz` with the arrays
+/// - `x_author`, `x_paper`, `x_term`: `(num_nodes, dim)` float features;
+///   `x_conference` is optional and defaults to one-hot features, with as many
+///   conferences as `1 +` the largest conference index of `paper__to__conference`, so
+///   trailing conferences without papers are dropped
+/// - `author__to__paper`, `paper__to__term`, `paper__to__conference`: `(2, num_edges)`
+///   integer indices, the first row indexing the first node type; the reverse
+///   relations are added
+/// - `y`: `(num_authors,)` integer labels
+/// - `train_idx`, `val_idx`, `test_idx`: integer author indices
+#[derive(Debug, Clone)]
+pub struct Dblp {
+    pub x: HashMap<NodeType, Tensor>,
+    pub edge_index: HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+    pub y: Tensor,
+    pub train_mask: Tensor,
+    pub val_mask: Tensor,
+    pub test_mask: Tensor,
+}
+impl Dblp {
+    pub const NUM_CLASSES: usize = 4;
+    pub const RELATIONS: [(NodeType, EdgeType, NodeType); 3] = [
+        (NodeType::Author, EdgeType::To, NodeType::Paper),
+        (NodeType::Paper, EdgeType::To, NodeType::Term),
+        (NodeType::Paper, EdgeType::To, NodeType::Conference),
+    ];
+
+    pub fn from_file<P: AsRef<Path>>(path: P, device: &Device) -> Result<Self> {
+        let npz = NpzReader::open(path)?;
+        let cpu = Device::Cpu;
+
+        let mut x = HashMap::new();
+        for node_type in NodeType::ALL {
+            let name = format!("x_{}", node_type);
+            let features = match (node_type, npz.get(&name, device)?) {
+                (_, Some(features)) => features.to_dtype(DType::F32)?,
+                (NodeType::Conference, None) => {
+                    let num_conferences = max_index(&npz.dense("paper__to__conference", &cpu)?, 1)?;
+                    Tensor::eye(num_conferences, DType::F32, device)?
+                }
+                (_, None) => anyhow::bail!("{} is missing in the npz", name),
+            };
+            x.insert(node_type, features);
+        }
+
+        let mut edge_index = HashMap::new();
+        for (first, rel, second) in Self::RELATIONS {
+            let index = npz
+                .dense(&format!("{}__{}__{}", first, rel, second), device)?
+                .to_dtype(DType::U32)?;
+            let reversed = Tensor::stack(&[index.get(1)?, index.get(0)?], 0)?;
+            edge_index.insert((first, rel, second), index);
+            edge_index.insert((second, rel, first), reversed);
+        }
+
+        let y = npz.dense("y", device)?.to_dtype(DType::U32)?;
+        let num_authors = y.dim(0)?;
+        let mask = |name: &str| -> Result<Tensor> {
+            let mut mask = vec![0u8; num_authors];
+            for i in npz
+                .dense(name, &cpu)?
+                .to_dtype(DType::U32)?
+                .to_vec1::<u32>()?
+            {
+                match mask.get_mut(i as usize) {
+                    Some(m) => *m = 1,
+                    None => anyhow::bail!(
+                        "{} contains {} but there are {} authors",
+                        name,
+                        i,
+                        num_authors
+                    ),
+                }
+            }
+            Ok(Tensor::from_vec(mask, num_authors, device)?)
+        };
+        Ok(Self {
+            x,
+            edge_index,
+            y,
+            train_mask: mask("train_idx")?,
+            val_mask: mask("val_idx")?,
+            test_mask: mask("test_idx")?,
+        })
+    }
+
+    /// Writes the arrays read by `from_file`.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut npz = NpzWriter::new();
+        for node_type in NodeType::ALL {
+            npz.add(&format!("x_{}", node_type), &self.x[&node_type])?;
+        }
+        for edge_type @ (first, rel, second) in Self::RELATIONS {
+            npz.add(
+                &format!("{}__{}__{}", first, rel, second),
+                &self.edge_index[&edge_type],
+            )?;
+        }
+        npz.add("y", &self.y)?;
+        for (name, mask) in [
+            ("train_idx", &self.train_mask),
+            ("val_idx", &self.val_mask),
+            ("test_idx", &self.test_mask),
+        ] {
+            npz.add(name, &mask_to_index(mask)?)?;
+        }
+        npz.write(path)
+    }
+
+    pub fn to_hetero_data(&self) -> Result<HeteroData<NodeType, EdgeType>> {
+        let mut data = HeteroData::new();
+        for (&node_type, x) in &self.x {
+            data.add_nodes(node_type, x.clone())?;
+        }
+        for (&edge_type, edge_index) in &self.edge_index {
+            data.add_edges(edge_type, edge_index.clone())?;
+        }
+        data.y.insert(NodeType::Author, self.y.clone());
+        data.train_mask
+            .insert(NodeType::Author, self.train_mask.clone());
+        data.val_mask
+            .insert(NodeType::Author, self.val_mask.clone());
+        data.test_mask
+            .insert(NodeType::Author, self.test_mask.clone());
+        Ok(data)
+    }
+}
+
+// 1 + the largest index in the given row
+fn max_index(edge_index: &Tensor, row: usize) -> Result<usize> {
+    let max = edge_index
+        .get(row)?
+        .to_dtype(DType::U32)?
+        .to_vec1::<u32>()?
+        .into_iter()
+        .max()
+        .map_or(0, |i| i as usize + 1);
+    Ok(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toy_dblp(device: &Device) -> Result<Dblp> {
+        let x = HashMap::from([
+            (NodeType::Author, Tensor::ones((3, 2), DType::F32, device)?),
+            (NodeType::Paper, Tensor::ones((4, 3), DType::F32, device)?),
+            (NodeType::Term, Tensor::ones((2, 1), DType::F32, device)?),
+            (NodeType::Conference, Tensor::eye(2, DType::F32, device)?),
+        ]);
+        let edge_index = HashMap::from([
+            (
+                Dblp::RELATIONS[0],
+                Tensor::new(&[[0u32, 1, 2, 2], [0, 1, 2, 3]], device)?,
+            ),
+            (
+                Dblp::RELATIONS[1],
+                Tensor::new(&[[0u32, 3], [1, 0]], device)?,
+            ),
+            (
+                Dblp::RELATIONS[2],
+                Tensor::new(&[[0u32, 1, 2, 3], [0, 0, 1, 1]], device)?,
+            ),
+        ]);
+        Ok(Dblp {
+            x,
+            edge_index,
+            y: Tensor::new(&[3u32, 0, 1], device)?,
+            train_mask: Tensor::new(&[1u8, 0, 0], device)?,
+            val_mask: Tensor::new(&[0u8, 1, 0], device)?,
+            test_mask: Tensor::new(&[0u8, 0, 1], device)?,
+        })
+    }
+
+    #[test]
+    fn test_dblp_roundtrip() -> Result<()> {
+        let device = Device::Cpu;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("dblp.npz");
+        let dblp = toy_dblp(&device)?;
+        dblp.to_file(&path)?;
+
+        let loaded = Dblp::from_file(&path, &device)?;
+        for node_type in NodeType::ALL {
+            assert_eq!(
+                loaded.x[&node_type].to_vec2::<f32>()?,
+                dblp.x[&node_type].to_vec2::<f32>()?
+            );
+        }
+        assert_eq!(loaded.edge_index.len(), 6);
+        for edge_type @ (first, rel, second) in Dblp::RELATIONS {
+            let index = dblp.edge_index[&edge_type].to_vec2::<u32>()?;
+            assert_eq!(loaded.edge_index[&edge_type].to_vec2::<u32>()?, index);
+            assert_eq!(
+                loaded.edge_index[&(second, rel, first)].to_vec2::<u32>()?,
+                [index[1].clone(), index[0].clone()]
+            );
+        }
+        assert_eq!(loaded.y.to_vec1::<u32>()?, [3, 0, 1]);
+        assert_eq!(loaded.train_mask.to_vec1::<u8>()?, [1, 0, 0]);
+        assert_eq!(loaded.val_mask.to_vec1::<u8>()?, [0, 1, 0]);
+        assert_eq!(loaded.test_mask.to_vec1::<u8>()?, [0, 0, 1]);
+
+        // without `x_conference`, one-hot features up to the largest conference index
+        let write_without_conference = |test_idx: u32| -> Result<()> {
+            let mut npz = NpzWriter::new();
+            for node_type in [NodeType::Author, NodeType::Paper, NodeType::Term] {
+                npz.add(&format!("x_{}", node_type), &dblp.x[&node_type])?;
+            }
+            for edge_type @ (first, rel, second) in Dblp::RELATIONS {
+                npz.add(
+                    &format!("{}__{}__{}", first, rel, second),
+                    &dblp.edge_index[&edge_type],
+                )?;
+            }
+            npz.add("y", &dblp.y)?
+                .add("train_idx", &Tensor::new(&[0u32], &device)?)?
+                .add("val_idx", &Tensor::new(&[1u32], &device)?)?
+                .add("test_idx", &Tensor::new(&[test_idx], &device)?)?
+                .write(&path)
+        };
+        write_without_conference(3)?;
+        assert!(Dblp::from_file(&path, &device).is_err());
+        write_without_conference(2)?;
+        let loaded = Dblp::from_file(&path, &device)?;
+        assert_eq!(
+            loaded.x[&NodeType::Conference].to_vec2::<f32>()?,
+            [[1.0, 0.0], [0.0, 1.0]]
+        );
+        Ok(())
+    }
+}
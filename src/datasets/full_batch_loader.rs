@@ -1,4 +1,5 @@
 use super::traits::Dataset;
+use anyhow::Result;
 use candle_core::Device;
 
 pub struct FullBatchLoader<'a, T> {
@@ -17,17 +18,16 @@ impl<'a, T: Dataset + 'a> FullBatchLoader<'a, T> {
 }
 
 impl<'a, T: Dataset> Iterator for FullBatchLoader<'a, T> {
-    type Item = T::Batch;
+    type Item = Result<T::Batch>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.done {
             None
         } else {
-            let all_nodes = self.dataset.all_nodes().unwrap();
+            self.done = true;
             let batch = self
                 .dataset
-                .induced_subgraph(all_nodes, self.device)
-                .unwrap();
-            self.done = true;
+                .all_nodes()
+                .and_then(|all_nodes| self.dataset.induced_subgraph(all_nodes, self.device));
             Some(batch)
         }
     }
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use polars::frame::DataFrame;
+
+use super::sampling::{subgraph, Adjacency, Rng};
+use super::traits::{Dataset, GraphBatch, PolarsDataset};
+use crate::EdgeIndex;
+
+/// How the nodes of a GraphSAINT subgraph are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaintSampler {
+    /// `budget` nodes drawn with replacement, proportionally to their degree.
+    Node { budget: usize },
+    /// Both ends of `budget` edges drawn with replacement, edge `(u, v)` with probability
+    /// proportional to `1 / deg(u) + 1 / deg(v)`.
+    Edge { budget: usize },
+    /// The nodes visited by random walks of `walk_length` steps from `roots` uniform
+    /// roots.
+    RandomWalk { roots: usize, walk_length: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphSaintLoaderParams {
+    pub sampler: SaintSampler,
+    /// Number of subgraphs per epoch.
+    pub num_steps: usize,
+    /// Subgraphs presampled to estimate the normalization coefficients, until each
+    /// node is sampled `sample_coverage` times on average; 0 disables the normalization.
+    pub sample_coverage: usize,
+    pub seed: Option<u64>,
+}
+impl Default for GraphSaintLoaderParams {
+    fn default() -> Self {
+        Self {
+            sampler: SaintSampler::RandomWalk {
+                roots: 3000,
+                walk_length: 2,
+            },
+            num_steps: 10,
+            sample_coverage: 50,
+            seed: None,
+        }
+    }
+}
+
+/// Batch of `GraphSaintLoader` with its normalization coefficients.
+#[derive(Debug, Clone)]
+pub struct SaintBatch<B> {
+    pub batch: B,
+    /// Loss normalization of shape `(num_nodes,)`; the loss of the masked nodes is
+    /// `(losses * node_norm.i(&batch.mask)?)?.sum_all()?`.
+    pub node_norm: Tensor,
+    /// Aggregation normalization of shape `(num_edges,)`, in the order of the batch
+    /// `edge_index`, to be passed as edge weights.
+    pub edge_norm: Tensor,
+}
+
+/// Mini-batch loader over sampled subgraphs (https://arxiv.org/abs/1907.04931).
+///
+/// Each batch is the subgraph induced by the sampled nodes, relabelled in the order of
+/// the node table. Its mask keeps the masked nodes of the dataset. The coefficients
+/// are estimated from the counts `c` of nodes and edges in presampled subgraphs:
+/// `node_norm[v] = num_samples / (c[v] * num_nodes)` and `edge_norm[(u, v)] = c[u] / c[(u, v)]`
+/// for the edge from `v` into `u`, making the aggregation and the loss unbiased.
+pub struct GraphSaintLoader<'a, D> {
+    dataset: &'a D,
+    device: &'a Device,
+    adjacency: Adjacency,
+    // row of each edge of `adjacency`
+    rows: Vec<u32>,
+    // cumulative sampling weights of the edges, for `SaintSampler::Edge`
+    cumulative: Vec<f64>,
+    mask: Vec<bool>,
+    node_norm: Vec<f32>,
+    edge_norm: Vec<f32>,
+    params: GraphSaintLoaderParams,
+    rng: Rng,
+}
+impl<'a, D> GraphSaintLoader<'a, D>
+where
+    D: Dataset<NodeSelector = DataFrame> + PolarsDataset,
+    D::Batch: GraphBatch,
+{
+    pub fn new(dataset: &'a D, params: GraphSaintLoaderParams, device: &'a Device) -> Result<Self> {
+        let adjacency = Adjacency::new(dataset)?;
+        let num_nodes = adjacency.num_nodes();
+        let num_edges = adjacency.num_edges();
+        let rows: Vec<u32> = (0..num_nodes as u32)
+            .flat_map(|i| std::iter::repeat_n(i, adjacency.degree(i)))
+            .collect();
+        let cumulative = match params.sampler {
+            SaintSampler::Edge { .. } => {
+                let mut sum = 0.0;
+                rows.iter()
+                    .zip(&adjacency.indices)
+                    .map(|(&i, &j)| {
+                        sum += 1.0 / adjacency.degree(i) as f64
+                            + 1.0 / adjacency.degree(j).max(1) as f64;
+                        sum
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let budget = match params.sampler {
+            SaintSampler::Node { budget } | SaintSampler::Edge { budget } => budget,
+            SaintSampler::RandomWalk { roots, .. } => roots,
+        };
+        if budget == 0 || num_nodes == 0 {
+            anyhow::bail!("cannot sample subgraphs with {:?}", params.sampler)
+        }
+        if let (SaintSampler::Edge { .. }, 0) = (params.sampler, num_edges) {
+            anyhow::bail!("cannot sample edges of a graph without edges")
+        }
+
+        let mask = dataset.node_df()["mask"]
+            .bool()?
+            .into_no_null_iter()
+            .collect();
+        let mut loader = Self {
+            dataset,
+            device,
+            adjacency,
+            rows,
+            cumulative,
+            mask,
+            node_norm: vec![1.0; num_nodes],
+            edge_norm: vec![1.0; num_edges],
+            rng: Rng::new(params.seed),
+            params,
+        };
+        if loader.params.sample_coverage > 0 {
+            loader.estimate_norms();
+        }
+        Ok(loader)
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.params.num_steps
+    }
+
+    /// Batches of one epoch.
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<SaintBatch<D::Batch>>> + use<'_, 'a, D> {
+        (0..self.params.num_steps).map(move |_| self.next_batch())
+    }
+
+    fn next_batch(&mut self) -> Result<SaintBatch<D::Batch>> {
+        let (nodes, edges) = self.sample();
+        let local: HashMap<u32, u32> = nodes
+            .iter()
+            .enumerate()
+            .map(|(k, &i)| (i, k as u32))
+            .collect();
+        let mut index = Vec::with_capacity(2 * edges.len());
+        index.extend(edges.iter().map(|&e| local[&self.rows[e]]));
+        index.extend(edges.iter().map(|&e| local[&self.adjacency.indices[e]]));
+        let edge_index = EdgeIndex::new(
+            Tensor::from_vec(index, (2, edges.len()), self.device)?,
+            nodes.len(),
+        )?;
+        let node_norm: Vec<f32> = nodes.iter().map(|&i| self.node_norm[i as usize]).collect();
+        let edge_norm: Vec<f32> = edges.iter().map(|&e| self.edge_norm[e]).collect();
+
+        let seeds: Vec<u32> = nodes
+            .iter()
+            .copied()
+            .filter(|&i| self.mask[i as usize])
+            .collect();
+        let dataset = self.dataset.with_edge_df(self.dataset.edge_df().clear());
+        let batch = subgraph(&dataset, &nodes, &seeds, self.device)?;
+        Ok(SaintBatch {
+            batch: batch.with_edge_index(edge_index),
+            node_norm: Tensor::from_vec(node_norm, nodes.len(), self.device)?,
+            edge_norm: Tensor::from_vec(edge_norm, edges.len(), self.device)?,
+        })
+    }
+
+    fn estimate_norms(&mut self) {
+        let num_nodes = self.adjacency.num_nodes();
+        let mut node_count = vec![0usize; num_nodes];
+        let mut edge_count = vec![0usize; self.adjacency.num_edges()];
+        let (mut num_samples, mut total) = (0, 0);
+        while total < self.params.sample_coverage * num_nodes {
+            let (nodes, edges) = self.sample();
+            for &i in &nodes {
+                node_count[i as usize] += 1;
+            }
+            for &e in &edges {
+                edge_count[e] += 1;
+            }
+            num_samples += 1;
+            total += nodes.len();
+        }
+        // never sampled in the presampling: the same fallback as PyG
+        self.node_norm = node_count
+            .iter()
+            .map(|&c| match c {
+                0 => 0.1,
+                c => num_samples as f32 / (c * num_nodes) as f32,
+            })
+            .collect();
+        self.edge_norm = edge_count
+            .iter()
+            .zip(&self.rows)
+            .map(|(&c, &i)| match c {
+                0 => 0.1,
+                c => (node_count[i as usize] as f32 / c as f32).min(1e4),
+            })
+            .collect();
+    }
+
+    // sorted positions of the sampled nodes and the edges of `adjacency` between them
+    fn sample(&mut self) -> (Vec<u32>, Vec<usize>) {
+        let num_nodes = self.adjacency.num_nodes();
+        let mut sampled = vec![false; num_nodes];
+        match self.params.sampler {
+            SaintSampler::Node { budget } => {
+                for _ in 0..budget {
+                    let e = self.rng.below(self.adjacency.num_edges().max(1));
+                    let i = match self.rows.get(e) {
+                        Some(&i) => i as usize,
+                        None => self.rng.below(num_nodes),
+                    };
+                    sampled[i] = true;
+                }
+            }
+            SaintSampler::Edge { budget } => {
+                let total = self.cumulative[self.cumulative.len() - 1];
+                for _ in 0..budget {
+                    let x = self.rng.uniform() * total;
+                    let e = self
+                        .cumulative
+                        .partition_point(|&c| c <= x)
+                        .min(self.cumulative.len() - 1);
+                    sampled[self.rows[e] as usize] = true;
+                    sampled[self.adjacency.indices[e] as usize] = true;
+                }
+            }
+            SaintSampler::RandomWalk { roots, walk_length } => {
+                for _ in 0..roots {
+                    let mut i = self.rng.below(num_nodes) as u32;
+                    sampled[i as usize] = true;
+                    for _ in 0..walk_length {
+                        let neighbors = self.adjacency.neighbors(i);
+                        if neighbors.is_empty() {
+                            break;
+                        }
+                        i = neighbors[self.rng.below(neighbors.len())];
+                        sampled[i as usize] = true;
+                    }
+                }
+            }
+        }
+        let nodes: Vec<u32> = (0..num_nodes as u32)
+            .filter(|&i| sampled[i as usize])
+            .collect();
+        let mut edges = Vec::new();
+        for &i in &nodes {
+            let start = self.adjacency.indptr[i as usize];
+            for (e, &j) in self.adjacency.neighbors(i).iter().enumerate() {
+                if sampled[j as usize] {
+                    edges.push(start + e);
+                }
+            }
+        }
+        (nodes, edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::datasets::sampling::tests::ToyDataset;
+
+    #[test]
+    fn test_graph_saint_loader() -> Result<()> {
+        let device = Device::Cpu;
+        // a cycle on 6 nodes in both directions, and the chord 0 -> 3
+        let mut edges: Vec<(u32, u32)> = (0..6).map(|i| (i, (i + 1) % 6)).collect();
+        edges.extend((0..6).map(|i| ((i + 1) % 6, i)));
+        edges.push((0, 3));
+        let dataset = ToyDataset::new(&[0; 6], &edges)?;
+        let edges: HashSet<(u32, u32)> = edges.into_iter().collect();
+        for sampler in [
+            SaintSampler::Node { budget: 3 },
+            SaintSampler::Edge { budget: 2 },
+            SaintSampler::RandomWalk {
+                roots: 2,
+                walk_length: 2,
+            },
+        ] {
+            let params = GraphSaintLoaderParams {
+                sampler,
+                num_steps: 5,
+                sample_coverage: 10,
+                seed: Some(0),
+            };
+            let mut loader = GraphSaintLoader::new(&dataset, params, &device)?;
+            for batch in loader.iter() {
+                let SaintBatch {
+                    batch,
+                    node_norm,
+                    edge_norm,
+                } = batch?;
+                let nodes = &batch.nodes;
+                assert!(nodes.windows(2).all(|w| w[0] < w[1]));
+                assert_eq!(batch.mask.len(), nodes.len());
+
+                let index = batch.edge_index.index().to_vec2::<u32>()?;
+                for (&i, &j) in index[0].iter().zip(&index[1]) {
+                    assert!((i as usize) < nodes.len() && (j as usize) < nodes.len());
+                    // messages flow from j into i, as in the dataset
+                    assert!(edges.contains(&(nodes[i as usize], nodes[j as usize])));
+                }
+                // the induced subgraph keeps all the edges between the sampled nodes
+                let num_induced = edges
+                    .iter()
+                    .filter(|(u, v)| nodes.contains(u) && nodes.contains(v))
+                    .count();
+                assert_eq!(index[0].len(), num_induced);
+
+                assert_eq!(node_norm.dims(), &[nodes.len()]);
+                assert_eq!(edge_norm.dims(), &[index[0].len()]);
+                for norm in [node_norm, edge_norm] {
+                    assert!(norm
+                        .to_vec1::<f32>()?
+                        .iter()
+                        .all(|x| x.is_finite() && *x > 0.0));
+                }
+            }
+        }
+        Ok(())
+    }
+}
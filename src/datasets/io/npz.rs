@@ -0,0 +1,250 @@
+//! NumPy `.npz` archives as written by `numpy.savez` and `scipy.sparse.save_npz`.
+
+use std::path::Path;
+
+use anyhow::Result;
+use candle_core::{npy::NpzTensors, DType, Device, IndexOp, Tensor};
+
+use crate::EdgeIndex;
+
+pub struct NpzReader {
+    npz: NpzTensors,
+}
+impl NpzReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            npz: NpzTensors::new(path)?,
+        })
+    }
+    pub fn names(&self) -> Vec<String> {
+        self.npz.names().into_iter().cloned().collect()
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.npz.names().iter().any(|n| *n == name)
+    }
+    /// Dense array `name`, or `None` if it is absent.
+    pub fn get(&self, name: &str, device: &Device) -> Result<Option<Tensor>> {
+        match self.npz.get(name)? {
+            Some(tensor) => Ok(Some(tensor.to_device(device)?)),
+            None => Ok(None),
+        }
+    }
+    /// Dense array `name`.
+    pub fn dense(&self, name: &str, device: &Device) -> Result<Tensor> {
+        self.get(name, device)?
+            .ok_or_else(|| anyhow::anyhow!("{} is missing in the npz", name))
+    }
+    /// CSR matrix stored as `{prefix}data`, `{prefix}indices`, `{prefix}indptr` and
+    /// `{prefix}shape`; the prefix is empty for a file of `scipy.sparse.save_npz`.
+    /// `data` is optional and defaults to ones.
+    pub fn csr(&self, prefix: &str, device: &Device) -> Result<CsrMatrix> {
+        let cpu = Device::Cpu;
+        let shape = self
+            .dense(&format!("{}shape", prefix), &cpu)?
+            .to_dtype(DType::U32)?
+            .to_vec1::<u32>()?;
+        let [num_rows, num_cols] = shape[..] else {
+            anyhow::bail!("{}shape must have two entries, got {:?}", prefix, shape)
+        };
+        let indptr = self
+            .dense(&format!("{}indptr", prefix), &cpu)?
+            .to_dtype(DType::U32)?;
+        let indices = self
+            .dense(&format!("{}indices", prefix), &cpu)?
+            .to_dtype(DType::U32)?;
+        let data = match self.get(&format!("{}data", prefix), &cpu)? {
+            Some(data) => data.to_dtype(DType::F32)?,
+            None => Tensor::ones(indices.dim(0)?, DType::F32, &cpu)?,
+        };
+        CsrMatrix::new(
+            indptr.to_device(device)?,
+            indices.to_device(device)?,
+            data.to_device(device)?,
+            (num_rows as usize, num_cols as usize),
+        )
+    }
+}
+
+/// Sparse matrix in compressed sparse row format.
+#[derive(Debug, Clone)]
+pub struct CsrMatrix {
+    /// `(num_rows + 1,)` u32 offsets of the rows into `indices` and `data`
+    pub indptr: Tensor,
+    /// `(nnz,)` u32 column indices
+    pub indices: Tensor,
+    /// `(nnz,)` values
+    pub data: Tensor,
+    pub shape: (usize, usize),
+}
+impl CsrMatrix {
+    pub fn new(
+        indptr: Tensor,
+        indices: Tensor,
+        data: Tensor,
+        shape: (usize, usize),
+    ) -> Result<Self> {
+        let offsets = indptr.to_vec1::<u32>()?;
+        let nnz = indices.dim(0)?;
+        if offsets.len() != shape.0 + 1
+            || offsets.first() != Some(&0)
+            || offsets.last() != Some(&(nnz as u32))
+            || offsets.windows(2).any(|w| w[0] > w[1])
+        {
+            anyhow::bail!("invalid indptr for {} rows and {} entries", shape.0, nnz)
+        }
+        if data.dim(0)? != nnz {
+            anyhow::bail!("{} values for {} entries", data.dim(0)?, nnz)
+        }
+        if let Some(&j) = indices
+            .to_vec1::<u32>()?
+            .iter()
+            .find(|&&j| j as usize >= shape.1)
+        {
+            anyhow::bail!("column index {} for {} columns", j, shape.1)
+        }
+        Ok(Self {
+            indptr,
+            indices,
+            data,
+            shape,
+        })
+    }
+    /// Row-major CSR of the edges, rows from `edge_index[0]`, with `edge_weight` or
+    /// ones as the values.
+    pub fn from_edge_index(edge_index: &EdgeIndex, edge_weight: Option<&Tensor>) -> Result<Self> {
+        let device = edge_index.device();
+        let rows = edge_index.index().to_vec2::<u32>()?;
+        let mut order: Vec<u32> = (0..edge_index.num_edges() as u32).collect();
+        order.sort_by_key(|&e| rows[0][e as usize]);
+        let mut indptr = vec![0u32; edge_index.num_nodes() + 1];
+        for &i in &rows[0] {
+            indptr[i as usize + 1] += 1;
+        }
+        for i in 0..edge_index.num_nodes() {
+            indptr[i + 1] += indptr[i];
+        }
+        let indices: Vec<u32> = order.iter().map(|&e| rows[1][e as usize]).collect();
+        let order = Tensor::new(order.as_slice(), device)?;
+        let data = match edge_weight {
+            Some(edge_weight) => edge_weight.i(&order)?,
+            None => Tensor::ones(order.dim(0)?, DType::F32, device)?,
+        };
+        let num_nodes = edge_index.num_nodes();
+        Self::new(
+            Tensor::new(indptr.as_slice(), device)?,
+            Tensor::new(indices.as_slice(), device)?,
+            data,
+            (num_nodes, num_nodes),
+        )
+    }
+    /// `(2, nnz)` u32 coordinates, row indices first, in row-major order.
+    pub fn to_coo(&self) -> Result<Tensor> {
+        let offsets = self.indptr.to_vec1::<u32>()?;
+        let rows: Vec<u32> = offsets
+            .windows(2)
+            .enumerate()
+            .flat_map(|(i, w)| std::iter::repeat_n(i as u32, (w[1] - w[0]) as usize))
+            .collect();
+        let rows = Tensor::new(rows.as_slice(), self.indices.device())?;
+        Ok(Tensor::stack(&[&rows, &self.indices], 0)?)
+    }
+    /// Edge index of a square matrix with rows as `edge_index[0]`, together with the
+    /// values as edge weights.
+    pub fn to_edge_index(&self) -> Result<(EdgeIndex, Tensor)> {
+        if self.shape.0 != self.shape.1 {
+            anyhow::bail!("edge index of a non-square {:?} matrix", self.shape)
+        }
+        Ok((
+            EdgeIndex::new(self.to_coo()?, self.shape.0)?,
+            self.data.clone(),
+        ))
+    }
+    pub fn to_dense(&self) -> Result<Tensor> {
+        let (num_rows, num_cols) = self.shape;
+        let coo = self.to_coo()?;
+        let flat = ((coo.i(0)?.to_dtype(DType::I64)? * num_cols as f64)?
+            + coo.i(1)?.to_dtype(DType::I64)?)?;
+        let zeros = Tensor::zeros(num_rows * num_cols, self.data.dtype(), self.data.device())?;
+        Ok(zeros
+            .index_add(&flat, &self.data, 0)?
+            .reshape((num_rows, num_cols))?)
+    }
+}
+
+/// Collects arrays and writes them with `numpy.savez` layout, so that `numpy.load`
+/// reads them back. Sparse matrices are stored as their `data`, `indices`, `indptr`
+/// and `shape` arrays, to be loaded with
+/// `scipy.sparse.csr_matrix((data, indices, indptr), shape)`.
+#[derive(Default)]
+pub struct NpzWriter {
+    tensors: Vec<(String, Tensor)>,
+}
+impl NpzWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, name: &str, tensor: &Tensor) -> Result<&mut Self> {
+        self.tensors
+            .push((name.to_string(), tensor.to_device(&Device::Cpu)?));
+        Ok(self)
+    }
+    /// The `(2, num_edges)` index as is.
+    pub fn add_edge_index(&mut self, name: &str, edge_index: &EdgeIndex) -> Result<&mut Self> {
+        self.add(name, edge_index.index())
+    }
+    pub fn add_csr(&mut self, prefix: &str, csr: &CsrMatrix) -> Result<&mut Self> {
+        let shape = Tensor::new(&[csr.shape.0 as i64, csr.shape.1 as i64], &Device::Cpu)?;
+        self.add(&format!("{}data", prefix), &csr.data)?
+            .add(&format!("{}indices", prefix), &csr.indices)?
+            .add(&format!("{}indptr", prefix), &csr.indptr)?
+            .add(&format!("{}shape", prefix), &shape)
+    }
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Tensor::write_npz(&self.tensors, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npz_roundtrip() -> Result<()> {
+        let device = Device::Cpu;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("graph.npz");
+
+        let x = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 6.]], &device)?;
+        let edge_index = EdgeIndex::new(Tensor::new(&[[2u32, 0, 2], [0, 1, 1]], &device)?, 3)?;
+        let edge_weight = Tensor::new(&[1f32, 2., 3.], &device)?;
+        let csr = CsrMatrix::from_edge_index(&edge_index, Some(&edge_weight))?;
+        assert_eq!(csr.indptr.to_vec1::<u32>()?, [0, 1, 1, 3]);
+        assert_eq!(
+            csr.to_dense()?.to_vec2::<f32>()?,
+            [[0., 2., 0.], [0., 0., 0.], [1., 3., 0.]]
+        );
+        NpzWriter::new()
+            .add("x", &x)?
+            .add_edge_index("edge_index", &edge_index)?
+            .add_csr("adj/", &csr)?
+            .write(&path)?;
+
+        let npz = NpzReader::open(&path)?;
+        assert!(npz.contains("x") && !npz.contains("y"));
+        assert_eq!(
+            npz.dense("x", &device)?.to_vec2::<f32>()?,
+            x.to_vec2::<f32>()?
+        );
+        assert_eq!(
+            npz.dense("edge_index", &device)?.to_vec2::<u32>()?,
+            [[2, 0, 2], [0, 1, 1]]
+        );
+        let (adj, weight) = npz.csr("adj/", &device)?.to_edge_index()?;
+        assert!(adj.is_sorted());
+        assert_eq!(adj.index().to_vec2::<u32>()?, [[0, 2, 2], [1, 0, 1]]);
+        assert_eq!(weight.to_vec1::<f32>()?, [2., 1., 3.]);
+        assert!(npz.csr("", &device).is_err());
+        Ok(())
+    }
+}
@@ -7,9 +7,37 @@ pub use cora::*;
 mod pubmed_diabetes;
 pub use pubmed_diabetes::*;
 
+mod node_classification;
+pub use node_classification::*;
+
+mod dblp;
+pub use dblp::*;
+
+pub mod io;
+
 mod full_batch_loader;
 pub use full_batch_loader::*;
 
+mod cached_loader;
+pub use cached_loader::*;
+
+mod cluster_loader;
+pub use cluster_loader::*;
+
+mod graph_saint;
+pub use graph_saint::*;
+
+mod neighbor_loader;
+pub use neighbor_loader::*;
+
+mod prefetch;
+pub use prefetch::*;
+
+mod sampling;
+
+mod splits;
+pub use splits::*;
+
 mod traits;
 pub use traits::*;
 
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use polars::frame::DataFrame;
+
+use super::sampling::{masked_positions, subgraph, Adjacency, Rng};
+use super::traits::{Dataset, GraphBatch, PolarsDataset};
+use crate::EdgeIndex;
+
+#[derive(Debug, Clone)]
+pub struct NeighborLoaderParams {
+    /// Number of neighbors sampled per node at each hop, `usize::MAX` for all of them.
+    pub fanouts: Vec<usize>,
+    pub batch_size: usize,
+    pub shuffle: bool,
+    pub seed: Option<u64>,
+}
+impl Default for NeighborLoaderParams {
+    fn default() -> Self {
+        Self {
+            fanouts: vec![25, 10],
+            batch_size: 512,
+            shuffle: true,
+            seed: None,
+        }
+    }
+}
+
+/// Mini-batch loader sampling the computational subgraph of the seed nodes
+/// (https://arxiv.org/abs/1706.02216).
+///
+/// Each batch holds the seeds first, then the nodes reached at each hop, relabelled in
+/// that order. Its `edge_index` contains the sampled edges only, and its `mask` and
+/// `ys` cover the seeds only, so the loss is computed as with `FullBatchLoader`.
+pub struct NeighborLoader<'a, D> {
+    dataset: &'a D,
+    device: &'a Device,
+    adjacency: Adjacency,
+    seeds: Vec<u32>,
+    params: NeighborLoaderParams,
+    rng: Rng,
+}
+impl<'a, D> NeighborLoader<'a, D>
+where
+    D: Dataset<NodeSelector = DataFrame> + PolarsDataset,
+    D::Batch: GraphBatch,
+{
+    /// Uses the masked nodes of `dataset` as seeds.
+    pub fn new(dataset: &'a D, params: NeighborLoaderParams, device: &'a Device) -> Result<Self> {
+        let seeds = masked_positions(dataset)?;
+        Self::with_seeds(dataset, &seeds, params, device)
+    }
+
+    /// Uses the nodes at the given rows of the node table as seeds.
+    pub fn with_seeds(
+        dataset: &'a D,
+        seeds: &[u32],
+        params: NeighborLoaderParams,
+        device: &'a Device,
+    ) -> Result<Self> {
+        if params.batch_size == 0 {
+            anyhow::bail!("batch size must be positive")
+        }
+        let adjacency = Adjacency::new(dataset)?;
+        if let Some(&i) = seeds.iter().find(|&&i| i as usize >= adjacency.num_nodes()) {
+            anyhow::bail!(
+                "seed {} out of range for {} nodes",
+                i,
+                adjacency.num_nodes()
+            )
+        }
+        let rng = Rng::new(params.seed);
+        Ok(Self {
+            dataset,
+            device,
+            adjacency,
+            seeds: seeds.to_vec(),
+            params,
+            rng,
+        })
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.seeds.len().div_ceil(self.params.batch_size)
+    }
+
+    /// Batches of one epoch, reshuffling the seeds if `shuffle` is set.
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<D::Batch>> + use<'_, 'a, D> {
+        let mut seeds = self.seeds.clone();
+        if self.params.shuffle {
+            self.rng.shuffle(&mut seeds);
+        }
+        let batch_size = self.params.batch_size;
+        (0..seeds.len()).step_by(batch_size).map(move |start| {
+            let end = (start + batch_size).min(seeds.len());
+            self.sample(&seeds[start..end])
+        })
+    }
+
+    fn sample(&mut self, seeds: &[u32]) -> Result<D::Batch> {
+        let mut nodes = Vec::new();
+        let mut local = HashMap::new();
+        for &i in seeds {
+            local.entry(i).or_insert_with(|| {
+                nodes.push(i);
+                nodes.len() as u32 - 1
+            });
+        }
+        let (mut rows, mut cols) = (Vec::new(), Vec::new());
+        let mut frontier = 0..nodes.len();
+        for &fanout in &self.params.fanouts {
+            let end = nodes.len();
+            for k in frontier {
+                let i = nodes[k];
+                for j in self.rng.sample(self.adjacency.neighbors(i), fanout) {
+                    let local_j = *local.entry(j).or_insert_with(|| {
+                        nodes.push(j);
+                        nodes.len() as u32 - 1
+                    });
+                    rows.push(k as u32);
+                    cols.push(local_j);
+                }
+            }
+            frontier = end..nodes.len();
+        }
+
+        let num_edges = rows.len();
+        rows.extend(cols);
+        let edge_index = EdgeIndex::new(
+            Tensor::from_vec(rows, (2, num_edges), self.device)?,
+            nodes.len(),
+        )?;
+        let dataset = self.dataset.with_edge_df(self.dataset.edge_df().clear());
+        let batch = subgraph(&dataset, &nodes, seeds, self.device)?;
+        Ok(batch.with_edge_index(edge_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::datasets::sampling::tests::ToyDataset;
+
+    #[test]
+    fn test_neighbor_loader() -> Result<()> {
+        let device = Device::Cpu;
+        let edges = [(0, 1), (0, 2), (1, 3), (2, 4), (3, 5), (4, 5), (5, 0)];
+        let dataset = ToyDataset::new(&[0; 6], &edges)?;
+        let edges: HashSet<(u32, u32)> = edges.into_iter().collect();
+        for fanout in [1, usize::MAX] {
+            let params = NeighborLoaderParams {
+                fanouts: vec![fanout, fanout],
+                batch_size: 2,
+                shuffle: false,
+                seed: Some(0),
+            };
+            let mut loader = NeighborLoader::with_seeds(&dataset, &[3, 0], params, &device)?;
+            assert_eq!(loader.num_batches(), 1);
+            for batch in loader.iter() {
+                let batch = batch?;
+                let nodes = &batch.nodes;
+                assert_eq!(nodes[..2], [3, 0]);
+                assert_eq!(batch.mask, [0, 1]);
+
+                let index = batch.edge_index.index().to_vec2::<u32>()?;
+                for (&i, &j) in index[0].iter().zip(&index[1]) {
+                    assert!((i as usize) < nodes.len() && (j as usize) < nodes.len());
+                    // messages flow from the sampled neighbor j into i
+                    assert!(edges.contains(&(nodes[i as usize], nodes[j as usize])));
+                }
+                if fanout == usize::MAX {
+                    // 3 -> 5, 0 -> 1, 0 -> 2, then 5 -> 0, 1 -> 3, 2 -> 4
+                    assert_eq!(index[0].len(), 6);
+                    assert_eq!(nodes.len(), 6);
+                } else {
+                    // one neighbor per node of the two frontiers
+                    assert_eq!(index[0].len(), 4);
+                }
+            }
+        }
+        Ok(())
+    }
+}
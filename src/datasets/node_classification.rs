@@ -0,0 +1,383 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufRead, BufReader},
+    marker::PhantomData,
+    path::Path,
+};
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use polars::{
+    chunked_array::ops::ChunkFull,
+    datatypes::BooleanChunked,
+    io::{
+        parquet::{ParquetReader, ParquetWriter},
+        SerReader,
+    },
+    prelude::{DataFrame, DataFrameJoinOps, NamedFromOwned, Series},
+};
+
+use super::io::npz::NpzWriter;
+use super::{download_and_extract, PolarsDataset};
+use super::{traits::Dataset, CompressionFormat, GraphBatch};
+use crate::EdgeIndex;
+
+#[derive(Debug, Clone)]
+pub struct NodeBatch {
+    pub xs: Tensor,
+    pub edge_index: EdgeIndex,
+    pub ys: Tensor,
+    pub mask: Tensor, // loss(&logits.i(mask)?, &ys)
+}
+impl GraphBatch for NodeBatch {
+    fn edge_index(&self) -> &EdgeIndex {
+        &self.edge_index
+    }
+    fn with_edge_index(self, edge_index: EdgeIndex) -> Self {
+        Self { edge_index, ..self }
+    }
+}
+
+/// Download location, sizes and parsers of the raw files of a `NodeClassificationDataset`.
+pub trait RawParser {
+    const URL: &'static str;
+    const COMPRESSION: CompressionFormat;
+    const NUM_FEATURES: usize;
+    const NUM_CLASSES: usize;
+    const NUM_NODES: usize;
+    const NUM_EDGES: usize;
+
+    /// Parses the edges into the columns `source` and `target`, which refer to `id`.
+    fn parse_edges(raw: &Path) -> Result<DataFrame>;
+    /// Parses the nodes into the columns `id`, `label` and the features `xs.{i}` (f32).
+    fn parse_nodes(raw: &Path) -> Result<DataFrame>;
+}
+
+/// Node classification dataset on a single graph, e.g., `CoraDataset`.
+///
+/// `prepare_data` downloads the archive into `root/raw` and parses it into
+/// `root/processed/{nodes,edges}.parquet`, once. Edges are made undirected when
+/// loaded, and labels are numbered in the order of their first appearance.
+#[derive(Debug, Clone)]
+pub struct NodeClassificationDataset<P> {
+    node_df: DataFrame,
+    edge_df: DataFrame,
+    parser: PhantomData<P>,
+}
+impl<P: RawParser> NodeClassificationDataset<P> {
+    pub fn prepare_data<Q: AsRef<Path>>(root: Q) -> anyhow::Result<()> {
+        let raw = root.as_ref().join("raw");
+        if !raw.exists() {
+            create_dir_all(&raw)?;
+            download_and_extract(P::URL, &raw, P::COMPRESSION)?;
+        }
+        let processed = root.as_ref().join("processed");
+        if !processed.exists() {
+            create_dir_all(&processed)?;
+            let mut edge_df = P::parse_edges(&raw)?;
+            anyhow::ensure!(
+                edge_df.height() == P::NUM_EDGES,
+                "expected {} edges, got {}",
+                P::NUM_EDGES,
+                edge_df.height()
+            );
+            ParquetWriter::new(File::create(processed.join("edges.parquet"))?)
+                .finish(&mut edge_df)?;
+
+            let mut node_df = P::parse_nodes(&raw)?;
+            anyhow::ensure!(
+                node_df.height() == P::NUM_NODES,
+                "expected {} nodes, got {}",
+                P::NUM_NODES,
+                node_df.height()
+            );
+            ParquetWriter::new(File::create(processed.join("nodes.parquet"))?)
+                .finish(&mut node_df)?;
+        }
+        Ok(())
+    }
+
+    pub fn from_processed<Q: AsRef<Path>>(root: Q) -> anyhow::Result<Self> {
+        let path = root.as_ref().join("processed");
+        let mut node_df = ParquetReader::new(File::open(path.join("nodes.parquet"))?).finish()?;
+        let mut edge_df = ParquetReader::new(File::open(path.join("edges.parquet"))?).finish()?;
+
+        // assign u32 label
+        let label = DataFrame::new(vec![node_df["label"].unique_stable()?])?
+            .with_row_count("label_u32", None)?;
+        node_df = node_df.inner_join(&label, ["label"], ["label"])?;
+
+        // assign mask
+        node_df.with_column(BooleanChunked::full("mask", true, node_df.height()))?;
+
+        // make undirectional
+        let mut rev_edge_df = edge_df.clone();
+        rev_edge_df.replace("source", edge_df["target"].clone())?;
+        rev_edge_df.replace("target", edge_df["source"].clone())?;
+        edge_df = edge_df.vstack(&rev_edge_df)?;
+        Ok(Self {
+            node_df,
+            edge_df,
+            parser: PhantomData,
+        })
+    }
+
+    pub fn new<Q: AsRef<Path>>(root: Q) -> anyhow::Result<Self> {
+        let root = root.as_ref();
+        Self::prepare_data(root)?;
+        Self::from_processed(root)
+    }
+    pub fn feature_cols(&self) -> Vec<String> {
+        (0..self.num_features())
+            .map(|i| format!("xs.{}", i))
+            .collect()
+    }
+    pub fn num_features(&self) -> usize {
+        P::NUM_FEATURES
+    }
+    pub fn num_classes(&self) -> usize {
+        P::NUM_CLASSES
+    }
+    fn id_cols(&self) -> &[&str] {
+        &["id"]
+    }
+
+    /// Writes the features `x`, the `edge_index` and the labels `y` of all the nodes,
+    /// in the order of `node_df`, and the positions of the masked nodes `mask_idx`.
+    pub fn to_file<Q: AsRef<Path>>(&self, path: Q) -> Result<()> {
+        let device = Device::Cpu;
+        let batch = self.induced_subgraph(self.all_nodes()?, &device)?;
+        let y = Tensor::from_iter(
+            self.node_df["label_u32"].u32()?.into_no_null_iter(),
+            &device,
+        )?;
+        NpzWriter::new()
+            .add("x", &batch.xs)?
+            .add_edge_index("edge_index", &batch.edge_index)?
+            .add("y", &y)?
+            .add("mask_idx", &batch.mask)?
+            .write(path)
+    }
+}
+
+impl<P> PolarsDataset for NodeClassificationDataset<P> {
+    fn node_df(&self) -> &DataFrame {
+        &self.node_df
+    }
+    fn edge_df(&self) -> &DataFrame {
+        &self.edge_df
+    }
+    fn with_node_df(&self, node_df: DataFrame) -> Self {
+        Self {
+            node_df,
+            edge_df: self.edge_df.clone(),
+            parser: PhantomData,
+        }
+    }
+    fn with_edge_df(&self, edge_df: DataFrame) -> Self {
+        Self {
+            node_df: self.node_df.clone(),
+            edge_df,
+            parser: PhantomData,
+        }
+    }
+}
+
+impl<P: RawParser> Dataset for NodeClassificationDataset<P> {
+    type Batch = NodeBatch;
+    type NodeSelector = DataFrame;
+
+    fn all_nodes(&self) -> Result<DataFrame> {
+        let result = self.node_df.select(self.id_cols())?;
+        Ok(result)
+    }
+    fn induced_subgraph(&self, nodes: DataFrame, device: &Device) -> Result<Self::Batch> {
+        let index = nodes.with_row_count("__index", None)?;
+
+        let node_df = index.inner_join(&self.node_df, ["id"], ["id"])?;
+        // the join does not keep the order of `nodes`
+        let node_df = node_df.sort(["__index"], false, false)?;
+        let mut xs = Vec::new();
+        for col in node_df.select_series(self.feature_cols())? {
+            xs.extend(col.f32()?.into_no_null_iter());
+        }
+        // column by column, so (num_features, num_nodes) transposed
+        let xs = Tensor::from_vec(xs, (self.num_features(), node_df.height()), device)?
+            .t()?
+            .contiguous()?;
+
+        let edge_df = self
+            .edge_df
+            .inner_join(&index, ["source"], ["id"])?
+            .inner_join(&index, ["target"], ["id"])?;
+        let mut edge_index = Vec::new();
+        edge_index.extend(edge_df["__index"].u32()?.into_no_null_iter());
+        edge_index.extend(edge_df["__index_right"].u32()?.into_no_null_iter());
+        let edge_index = EdgeIndex::new(
+            Tensor::from_vec(edge_index, (2, edge_df.height()), device)?,
+            index.height(),
+        )?;
+
+        let masked_node_df = node_df.filter(node_df["mask"].bool()?)?;
+        let ys = Tensor::from_iter(
+            masked_node_df["label_u32"].u32()?.into_no_null_iter(),
+            device,
+        )?;
+        let mask = Tensor::from_iter(masked_node_df["__index"].u32()?.into_no_null_iter(), device)?;
+
+        Ok(Self::Batch {
+            xs,
+            edge_index,
+            ys,
+            mask,
+        })
+    }
+}
+
+/// Reads a LINQS `.cites` file: one pair of ids per line, the cited paper first.
+pub(crate) fn read_linqs_cites(path: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let e = || anyhow::anyhow!("Exhausted Iterator");
+    let reader = BufReader::new(File::open(path)?);
+    let mut source = Vec::new();
+    let mut target = Vec::new();
+    for buf in reader.lines() {
+        let line = buf?;
+        let mut iter = line.split_whitespace();
+        source.push(iter.next().ok_or_else(e)?.to_owned());
+        target.push(iter.next().ok_or_else(e)?.to_owned());
+        anyhow::ensure!(iter.next().is_none(), "unexpected entry in {:?}", line);
+    }
+    Ok((source, target))
+}
+
+/// Ids, feature columns and labels of a LINQS `.content` file.
+pub(crate) type LinqsContent = (Vec<String>, Vec<Vec<f32>>, Vec<String>);
+
+/// Reads a LINQS `.content` file: `id`, the features and `label` per line.
+pub(crate) fn read_linqs_content(path: &Path, num_features: usize) -> Result<LinqsContent> {
+    let e = || anyhow::anyhow!("Exhausted Iterator");
+    let reader = BufReader::new(File::open(path)?);
+    let mut id = Vec::new();
+    let mut xs = vec![Vec::new(); num_features];
+    let mut label = Vec::new();
+    for buf in reader.lines() {
+        let line = buf?;
+        let mut iter = line.split_whitespace();
+        id.push(iter.next().ok_or_else(e)?.to_owned());
+        for xs_i in xs.iter_mut() {
+            xs_i.push(iter.next().ok_or_else(e)?.parse::<f32>()?);
+        }
+        label.push(iter.next().ok_or_else(e)?.to_owned());
+        anyhow::ensure!(iter.next().is_none(), "unexpected entry in {:?}", line);
+    }
+    Ok((id, xs, label))
+}
+
+/// Appends the feature columns `xs.{i}`.
+pub(crate) fn with_features(mut node_df: DataFrame, xs: Vec<Vec<f32>>) -> Result<DataFrame> {
+    for (i, x) in xs.into_iter().enumerate() {
+        let name = format!("xs.{}", i);
+        node_df.with_column(Series::from_vec(&name, x))?;
+    }
+    Ok(node_df)
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{df, NamedFrom};
+
+    use super::*;
+    use crate::datasets::io::npz::NpzReader;
+
+    /// In-memory parser with two features and two classes; the dataset is built by
+    /// `toy_dataset`, never downloaded or parsed.
+    #[derive(Debug, Clone, Copy)]
+    struct Toy;
+    impl RawParser for Toy {
+        const URL: &'static str = "";
+        const COMPRESSION: CompressionFormat = CompressionFormat::Zip;
+        const NUM_FEATURES: usize = 2;
+        const NUM_CLASSES: usize = 2;
+        const NUM_NODES: usize = 0;
+        const NUM_EDGES: usize = 0;
+
+        fn parse_edges(_: &Path) -> Result<DataFrame> {
+            anyhow::bail!("in-memory test dataset")
+        }
+        fn parse_nodes(_: &Path) -> Result<DataFrame> {
+            anyhow::bail!("in-memory test dataset")
+        }
+    }
+
+    /// Masked nodes with ids `100 + i`, features `[i, 10 i]` and the given labels,
+    /// and the directed `edges` between the positions of the nodes.
+    fn toy_dataset(labels: &[u32], edges: &[(u32, u32)]) -> Result<NodeClassificationDataset<Toy>> {
+        if let Some(&label) = labels.iter().find(|&&l| l as usize >= Toy::NUM_CLASSES) {
+            anyhow::bail!("label {} but there are {} classes", label, Toy::NUM_CLASSES)
+        }
+        let num_nodes = labels.len();
+        let node_df = df! {
+            "id" => (0..num_nodes as u32).map(|i| 100 + i).collect::<Vec<_>>(),
+            "label" => labels.to_vec(),
+            "xs.0" => (0..num_nodes).map(|i| i as f32).collect::<Vec<_>>(),
+            "xs.1" => (0..num_nodes).map(|i| 10.0 * i as f32).collect::<Vec<_>>(),
+            "label_u32" => labels.to_vec(),
+            "mask" => vec![true; num_nodes],
+        }?;
+        let edge_df = df! {
+            "source" => edges.iter().map(|&(u, _)| 100 + u).collect::<Vec<_>>(),
+            "target" => edges.iter().map(|&(_, v)| 100 + v).collect::<Vec<_>>(),
+        }?;
+        Ok(NodeClassificationDataset {
+            node_df,
+            edge_df,
+            parser: PhantomData,
+        })
+    }
+
+    #[test]
+    fn test_induced_subgraph() -> Result<()> {
+        let device = Device::Cpu;
+        let dataset = toy_dataset(&[0, 1, 1], &[(0, 1), (1, 2), (2, 0)])?;
+        assert_eq!(dataset.num_classes(), 2);
+        let batch = dataset.induced_subgraph(dataset.all_nodes()?, &device)?;
+        assert_eq!(
+            batch.xs.to_vec2::<f32>()?,
+            [[0.0, 0.0], [1.0, 10.0], [2.0, 20.0]]
+        );
+        assert_eq!(batch.ys.to_vec1::<u32>()?, [0, 1, 1]);
+
+        // relabelled in the order of the selection
+        let nodes = df! { "id" => [102u32, 100] }?;
+        let batch = dataset.induced_subgraph(nodes, &device)?;
+        assert_eq!(batch.xs.to_vec2::<f32>()?, [[2.0, 20.0], [0.0, 0.0]]);
+        assert_eq!(batch.edge_index.index().to_vec2::<u32>()?, [[0], [1]]);
+        assert_eq!(batch.mask.to_vec1::<u32>()?, [0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_file() -> Result<()> {
+        let device = Device::Cpu;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("toy.npz");
+        let dataset = toy_dataset(&[0, 1, 1], &[(0, 1), (1, 2), (2, 0)])?;
+        let mut node_df = dataset.node_df().clone();
+        node_df.replace("mask", Series::new("mask", [true, false, true]))?;
+        dataset.with_node_df(node_df).to_file(&path)?;
+
+        let npz = NpzReader::open(&path)?;
+        assert_eq!(
+            npz.dense("x", &device)?.to_vec2::<f32>()?,
+            [[0.0, 0.0], [1.0, 10.0], [2.0, 20.0]]
+        );
+        // the join does not keep the order of the edges
+        let index = npz.dense("edge_index", &device)?.to_vec2::<u32>()?;
+        let mut edges: Vec<_> = index[0].iter().zip(&index[1]).collect();
+        edges.sort();
+        assert_eq!(edges, [(&0, &1), (&1, &2), (&2, &0)]);
+        assert_eq!(npz.dense("y", &device)?.to_vec1::<u32>()?, [0, 1, 1]);
+        assert_eq!(npz.dense("mask_idx", &device)?.to_vec1::<u32>()?, [0, 2]);
+        Ok(())
+    }
+}
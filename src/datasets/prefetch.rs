@@ -0,0 +1,65 @@
+use std::{
+    sync::mpsc::{sync_channel, Receiver},
+    thread::Scope,
+};
+
+/// Iterator over the items of another iterator run on a background thread.
+pub struct Prefetch<T> {
+    receiver: Receiver<T>,
+}
+impl<T> Iterator for Prefetch<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Runs `loader` on a thread of `scope`, preparing up to `buffer` batches ahead of the
+/// consumer, e.g., the next batches of a `NeighborLoader` while the model trains.
+///
+/// ```ignore
+/// std::thread::scope(|s| {
+///     for batch in prefetch(s, loader.iter(), 2) {
+///         let batch = batch?;
+///         // ...
+///     }
+///     Ok(())
+/// })
+/// ```
+///
+/// The thread stops when the returned iterator is dropped. If it panics, the iteration
+/// ends early and the panic is propagated when the scope ends.
+pub fn prefetch<'scope, I>(
+    scope: &'scope Scope<'scope, '_>,
+    loader: I,
+    buffer: usize,
+) -> Prefetch<I::Item>
+where
+    I: Iterator + Send + 'scope,
+    I::Item: Send + 'scope,
+{
+    let (sender, receiver) = sync_channel(buffer);
+    scope.spawn(move || {
+        for item in loader {
+            if sender.send(item).is_err() {
+                break;
+            }
+        }
+    });
+    Prefetch { receiver }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefetch() {
+        let items: Vec<_> = std::thread::scope(|s| prefetch(s, 0..10, 2).collect());
+        assert_eq!(items, (0..10).collect::<Vec<_>>());
+
+        // dropping the iterator stops the thread
+        let first = std::thread::scope(|s| prefetch(s, 0.., 1).next());
+        assert_eq!(first, Some(0));
+    }
+}
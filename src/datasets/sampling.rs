@@ -0,0 +1,258 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use polars::{
+    frame::DataFrame,
+    prelude::{BooleanChunked, DataFrameJoinOps, IdxCa, NewChunkedArray},
+};
+
+use super::{Dataset, PolarsDataset};
+
+/// SplitMix64 generator for the samplers; seeded, so that the batches are reproducible.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+impl Rng {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        }))
+    }
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    /// Uniform in `0..n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    /// Uniform in `[0, 1)`.
+    pub(crate) fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    pub(crate) fn shuffle<T>(&mut self, xs: &mut [T]) {
+        for i in (1..xs.len()).rev() {
+            xs.swap(i, self.below(i + 1));
+        }
+    }
+    /// `k` distinct elements of `xs`, or all of them if `k >= xs.len()`.
+    pub(crate) fn sample<T: Copy>(&mut self, xs: &[T], k: usize) -> Vec<T> {
+        if k >= xs.len() {
+            return xs.to_vec();
+        }
+        let mut xs = xs.to_vec();
+        for i in 0..k {
+            let j = i + self.below(xs.len() - i);
+            xs.swap(i, j);
+        }
+        xs.truncate(k);
+        xs
+    }
+}
+
+/// Adjacency of a `PolarsDataset` in CSR format over the positions of the nodes in
+/// `node_df`. The row of an edge is its `source`, i.e., `edge_index[0]` in the batches.
+#[derive(Debug, Clone)]
+pub(crate) struct Adjacency {
+    pub(crate) indptr: Vec<usize>,
+    pub(crate) indices: Vec<u32>,
+}
+impl Adjacency {
+    pub(crate) fn new<D>(dataset: &D) -> Result<Self>
+    where
+        D: Dataset<NodeSelector = DataFrame> + PolarsDataset,
+    {
+        let index = dataset.all_nodes()?.with_row_count("__index", None)?;
+        let edge_df = dataset
+            .edge_df()
+            .inner_join(&index, ["source"], ["id"])?
+            .inner_join(&index, ["target"], ["id"])?;
+        let rows: Vec<u32> = edge_df["__index"].u32()?.into_no_null_iter().collect();
+        let cols: Vec<u32> = edge_df["__index_right"]
+            .u32()?
+            .into_no_null_iter()
+            .collect();
+        Ok(Self::from_edges(index.height(), &rows, &cols))
+    }
+    pub(crate) fn from_edges(num_nodes: usize, rows: &[u32], cols: &[u32]) -> Self {
+        let mut indptr = vec![0; num_nodes + 1];
+        for &i in rows {
+            indptr[i as usize + 1] += 1;
+        }
+        for i in 0..num_nodes {
+            indptr[i + 1] += indptr[i];
+        }
+        let mut fill = indptr.clone();
+        let mut indices = vec![0; rows.len()];
+        for (&i, &j) in rows.iter().zip(cols) {
+            indices[fill[i as usize]] = j;
+            fill[i as usize] += 1;
+        }
+        Self { indptr, indices }
+    }
+    pub(crate) fn num_nodes(&self) -> usize {
+        self.indptr.len() - 1
+    }
+    pub(crate) fn num_edges(&self) -> usize {
+        self.indices.len()
+    }
+    pub(crate) fn degree(&self, i: u32) -> usize {
+        self.indptr[i as usize + 1] - self.indptr[i as usize]
+    }
+    pub(crate) fn neighbors(&self, i: u32) -> &[u32] {
+        &self.indices[self.indptr[i as usize]..self.indptr[i as usize + 1]]
+    }
+}
+
+/// Positions of the nodes whose `mask` is set.
+pub(crate) fn masked_positions<D: PolarsDataset>(dataset: &D) -> Result<Vec<u32>> {
+    Ok(dataset.node_df()["mask"]
+        .bool()?
+        .into_no_null_iter()
+        .enumerate()
+        .filter_map(|(i, m)| m.then_some(i as u32))
+        .collect())
+}
+
+/// Induced subgraph on the nodes at `positions`, relabelled in that order, where only
+/// the nodes at `seeds` keep their mask.
+pub(crate) fn subgraph<D>(
+    dataset: &D,
+    positions: &[u32],
+    seeds: &[u32],
+    device: &candle_core::Device,
+) -> Result<D::Batch>
+where
+    D: Dataset<NodeSelector = DataFrame> + PolarsDataset,
+{
+    let mut mask = vec![false; dataset.node_df().height()];
+    for &i in seeds {
+        mask[i as usize] = true;
+    }
+    let mut node_df = dataset.node_df().clone();
+    node_df.replace_or_add("mask", BooleanChunked::from_slice("mask", &mask))?;
+    let nodes = dataset
+        .all_nodes()?
+        .take(&IdxCa::from_vec("", positions.to_vec()))?;
+    dataset
+        .with_node_df(node_df)
+        .induced_subgraph(nodes, device)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{Device, Tensor};
+    use polars::prelude::{df, NamedFrom};
+
+    use super::*;
+    use crate::datasets::GraphBatch;
+    use crate::EdgeIndex;
+
+    /// Batch of `ToyDataset`, relabelled in the order of `nodes`.
+    #[derive(Debug, Clone)]
+    pub(crate) struct ToyBatch {
+        /// Positions of the nodes in the dataset
+        pub(crate) nodes: Vec<u32>,
+        pub(crate) edge_index: EdgeIndex,
+        /// Local indices of the masked nodes
+        pub(crate) mask: Vec<u32>,
+    }
+    impl GraphBatch for ToyBatch {
+        fn edge_index(&self) -> &EdgeIndex {
+            &self.edge_index
+        }
+        fn with_edge_index(self, edge_index: EdgeIndex) -> Self {
+            Self { edge_index, ..self }
+        }
+    }
+
+    /// In-memory dataset for the loader tests: masked nodes with ids `100 + i` and the
+    /// labels `label_u32`, and the directed `edges` between the positions of the nodes.
+    #[derive(Debug, Clone)]
+    pub(crate) struct ToyDataset {
+        node_df: DataFrame,
+        edge_df: DataFrame,
+    }
+    impl ToyDataset {
+        pub(crate) fn new(labels: &[u32], edges: &[(u32, u32)]) -> Result<Self> {
+            let node_df = df! {
+                "id" => (0..labels.len() as u32).map(|i| 100 + i).collect::<Vec<_>>(),
+                "label_u32" => labels.to_vec(),
+                "mask" => vec![true; labels.len()],
+            }?;
+            let edge_df = df! {
+                "source" => edges.iter().map(|&(u, _)| 100 + u).collect::<Vec<_>>(),
+                "target" => edges.iter().map(|&(_, v)| 100 + v).collect::<Vec<_>>(),
+            }?;
+            Ok(Self { node_df, edge_df })
+        }
+    }
+    impl PolarsDataset for ToyDataset {
+        fn node_df(&self) -> &DataFrame {
+            &self.node_df
+        }
+        fn edge_df(&self) -> &DataFrame {
+            &self.edge_df
+        }
+        fn with_node_df(&self, node_df: DataFrame) -> Self {
+            Self {
+                node_df,
+                edge_df: self.edge_df.clone(),
+            }
+        }
+        fn with_edge_df(&self, edge_df: DataFrame) -> Self {
+            Self {
+                node_df: self.node_df.clone(),
+                edge_df,
+            }
+        }
+    }
+    impl Dataset for ToyDataset {
+        type Batch = ToyBatch;
+        type NodeSelector = DataFrame;
+
+        fn all_nodes(&self) -> Result<DataFrame> {
+            Ok(self.node_df.select(["id"])?)
+        }
+        fn induced_subgraph(&self, nodes: DataFrame, device: &Device) -> Result<ToyBatch> {
+            let nodes: Vec<u32> = nodes["id"]
+                .u32()?
+                .into_no_null_iter()
+                .map(|id| id - 100)
+                .collect();
+            let local: HashMap<u32, u32> = nodes
+                .iter()
+                .enumerate()
+                .map(|(k, &i)| (100 + i, k as u32))
+                .collect();
+            let (mut rows, mut cols) = (Vec::new(), Vec::new());
+            let source = self.edge_df["source"].u32()?;
+            let target = self.edge_df["target"].u32()?;
+            for (u, v) in source.into_no_null_iter().zip(target.into_no_null_iter()) {
+                if let (Some(&k), Some(&l)) = (local.get(&u), local.get(&v)) {
+                    rows.push(k);
+                    cols.push(l);
+                }
+            }
+            let num_edges = rows.len();
+            rows.extend(cols);
+            let edge_index =
+                EdgeIndex::new(Tensor::from_vec(rows, (2, num_edges), device)?, nodes.len())?;
+            let masked: Vec<bool> = self.node_df["mask"].bool()?.into_no_null_iter().collect();
+            let mask = (0..nodes.len() as u32)
+                .filter(|&k| masked[nodes[k as usize] as usize])
+                .collect();
+            Ok(ToyBatch {
+                nodes,
+                edge_index,
+                mask,
+            })
+        }
+    }
+}
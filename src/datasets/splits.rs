@@ -0,0 +1,289 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufRead, BufReader},
+    ops::BitAnd,
+    path::Path,
+};
+
+use anyhow::Result;
+use candle_core::{DType, Device};
+use polars::prelude::{BooleanChunked, DataType, NewChunkedArray};
+
+use super::io::npz::NpzReader;
+use super::sampling::{masked_positions, Rng};
+use super::PolarsDataset;
+
+/// Label-aware splits of the masked nodes, e.g., the Planetoid splits
+/// (https://arxiv.org/abs/1603.08861).
+///
+/// The labels are read from the `label_u32` column of `node_df`. As with `RandomSplit`,
+/// each split keeps the mask of the dataset and the nodes outside of it are unmasked.
+pub trait NodeSplit: PolarsDataset + Sized {
+    /// `num_train_per_class` random nodes of each class for training, then
+    /// `num_val` and `num_test` random nodes among the rest, e.g., `(20, 500, 1000)`.
+    fn per_class_split(
+        &self,
+        num_train_per_class: usize,
+        num_val: usize,
+        num_test: usize,
+        seed: Option<u64>,
+    ) -> Result<[Self; 3]> {
+        let mut rng = Rng::new(seed);
+        let mut train = Vec::new();
+        let mut rest = Vec::new();
+        for (label, mut nodes) in nodes_by_class(self)? {
+            if nodes.len() < num_train_per_class {
+                anyhow::bail!(
+                    "class {} has {} nodes but {} are required for training",
+                    label,
+                    nodes.len(),
+                    num_train_per_class
+                )
+            }
+            rng.shuffle(&mut nodes);
+            rest.extend(nodes.split_off(num_train_per_class));
+            train.extend(nodes);
+        }
+        if rest.len() < num_val + num_test {
+            anyhow::bail!(
+                "{} nodes remain but {} are required for validation and test",
+                rest.len(),
+                num_val + num_test
+            )
+        }
+        rng.shuffle(&mut rest);
+        let test = rest.split_off(num_val).into_iter().take(num_test).collect();
+        with_splits(self, [train, rest, test])
+    }
+
+    /// Splits each class according to `ratio`, so that every split has the label
+    /// distribution of the dataset.
+    fn stratified_split<const N: usize>(
+        &self,
+        ratio: [f32; N],
+        seed: Option<u64>,
+    ) -> Result<[Self; N]> {
+        let mut rng = Rng::new(seed);
+        let mut splits: [Vec<u32>; N] = std::array::from_fn(|_| Vec::new());
+        for (_, mut nodes) in nodes_by_class(self)? {
+            rng.shuffle(&mut nodes);
+            let mut cumsum = 0.0;
+            let mut start = 0;
+            for (split, f) in splits.iter_mut().zip(ratio) {
+                cumsum += f;
+                let end = ((cumsum * nodes.len() as f32).round() as usize).min(nodes.len());
+                split.extend(&nodes[start.min(end)..end]);
+                start = end;
+            }
+        }
+        with_splits(self, splits)
+    }
+
+    /// Reads the public split from a local `.npz` with the integer arrays `train_idx`,
+    /// `val_idx` and `test_idx`, as written by `numpy.savez`.
+    ///
+    /// The arrays index the rows of `node_df`, i.e., the order of the nodes in the raw
+    /// files, e.g., the lines of `cora.content`. The Planetoid `ind.*` files order the
+    /// nodes differently; a split in that order goes through `public_split_by_id`.
+    fn public_split<P: AsRef<Path>>(&self, path: P) -> Result<[Self; 3]> {
+        let npz = NpzReader::open(path)?;
+        let num_nodes = self.node_df().height();
+        let read = |name: &str| -> Result<Vec<u32>> {
+            let index = npz
+                .dense(name, &Device::Cpu)?
+                .to_dtype(DType::U32)?
+                .flatten_all()?
+                .to_vec1::<u32>()?;
+            if let Some(&i) = index.iter().find(|&&i| i as usize >= num_nodes) {
+                anyhow::bail!("{} contains {} but there are {} nodes", name, i, num_nodes)
+            }
+            Ok(index)
+        };
+        with_splits(
+            self,
+            [read("train_idx")?, read("val_idx")?, read("test_idx")?],
+        )
+    }
+
+    /// Reads a public split from a text file with one `id split` pair per line, where
+    /// `id` is a value of the `id` column and `split` is `train`, `val` or `test`.
+    fn public_split_by_id<P: AsRef<Path>>(&self, path: P) -> Result<[Self; 3]> {
+        let ids = self.node_df()["id"].cast(&DataType::Utf8)?;
+        let positions: HashMap<&str, u32> = ids
+            .utf8()?
+            .into_no_null_iter()
+            .enumerate()
+            .map(|(i, id)| (id, i as u32))
+            .collect();
+        let mut listed = vec![false; ids.len()];
+        let mut splits: [Vec<u32>; 3] = Default::default();
+        for buf in BufReader::new(File::open(path)?).lines() {
+            let line = buf?;
+            let mut iter = line.split_whitespace();
+            let (id, split) = match (iter.next(), iter.next(), iter.next()) {
+                (None, ..) => continue,
+                (Some(id), Some(split), None) => (id, split),
+                _ => anyhow::bail!("expected `id split`, got {:?}", line),
+            };
+            let Some(&i) = positions.get(id) else {
+                anyhow::bail!("node {} is not in the dataset", id)
+            };
+            let k = match split {
+                "train" => 0,
+                "val" => 1,
+                "test" => 2,
+                _ => anyhow::bail!("unknown split {:?} for node {}", split, id),
+            };
+            if std::mem::replace(&mut listed[i as usize], true) {
+                anyhow::bail!("node {} is listed twice", id)
+            }
+            splits[k].push(i);
+        }
+        with_splits(self, splits)
+    }
+}
+impl<D: PolarsDataset> NodeSplit for D {}
+
+// masked nodes grouped by label, in the order of `node_df`
+fn nodes_by_class<D: PolarsDataset>(dataset: &D) -> Result<BTreeMap<u32, Vec<u32>>> {
+    let labels: Vec<u32> = dataset.node_df()["label_u32"]
+        .u32()?
+        .into_no_null_iter()
+        .collect();
+    let mut classes: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for i in masked_positions(dataset)? {
+        classes.entry(labels[i as usize]).or_default().push(i);
+    }
+    Ok(classes)
+}
+
+fn with_splits<D: PolarsDataset, const N: usize>(
+    dataset: &D,
+    splits: [Vec<u32>; N],
+) -> Result<[D; N]> {
+    let num_nodes = dataset.node_df().height();
+    let mut result = Vec::new();
+    for split in splits {
+        let mut mask = vec![false; num_nodes];
+        for i in split {
+            mask[i as usize] = true;
+        }
+        let mask = BooleanChunked::from_slice("mask", &mask);
+        let mut node_df = dataset.node_df().clone();
+        node_df.replace_or_add("mask", node_df["mask"].bool()?.bitand(&mask))?;
+        result.push(dataset.with_node_df(node_df));
+    }
+    match result.try_into() {
+        Ok(result) => Ok(result),
+        Err(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use candle_core::Tensor;
+
+    use super::*;
+    use crate::datasets::io::npz::NpzWriter;
+    use crate::datasets::sampling::tests::ToyDataset;
+
+    fn labels_of<D: PolarsDataset>(split: &D) -> Result<Vec<u32>> {
+        let labels: Vec<u32> = split.node_df()["label_u32"]
+            .u32()?
+            .into_no_null_iter()
+            .collect();
+        Ok(masked_positions(split)?
+            .into_iter()
+            .map(|i| labels[i as usize])
+            .collect())
+    }
+
+    #[test]
+    fn test_per_class_split() -> Result<()> {
+        let labels = [0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2];
+        let dataset = ToyDataset::new(&labels, &[])?;
+        let [train, val, test] = dataset.per_class_split(2, 3, 2, Some(0))?;
+        let mut train_labels = labels_of(&train)?;
+        train_labels.sort();
+        assert_eq!(train_labels, [0, 0, 1, 1, 2, 2]);
+
+        let mut seen = HashSet::new();
+        for (split, size) in [(&train, 6), (&val, 3), (&test, 2)] {
+            let positions = masked_positions(split)?;
+            assert_eq!(positions.len(), size);
+            assert!(positions.into_iter().all(|i| seen.insert(i)));
+        }
+
+        // class 2 has 3 nodes, and 12 - 3 * 2 nodes remain after the training nodes
+        assert!(dataset.per_class_split(4, 0, 0, Some(0)).is_err());
+        assert!(dataset.per_class_split(2, 4, 3, Some(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stratified_split() -> Result<()> {
+        let labels: Vec<u32> = (0..30).map(|i| (i >= 10) as u32).collect();
+        let dataset = ToyDataset::new(&labels, &[])?;
+        let splits = dataset.stratified_split([0.5, 0.3, 0.2], Some(0))?;
+        for (split, expected) in splits.iter().zip([(5, 10), (3, 6), (2, 4)]) {
+            let labels = labels_of(split)?;
+            let ones = labels.iter().filter(|&&label| label == 1).count();
+            assert_eq!((labels.len() - ones, ones), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_split() -> Result<()> {
+        let device = Device::Cpu;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("split.npz");
+        let dataset = ToyDataset::new(&[0, 1, 0, 1, 0], &[])?;
+
+        // int64 arrays, as `numpy.savez` writes them
+        NpzWriter::new()
+            .add("train_idx", &Tensor::new(&[3i64, 0], &device)?)?
+            .add("val_idx", &Tensor::new(&[1i64], &device)?)?
+            .add("test_idx", &Tensor::new(&[2i64, 4], &device)?)?
+            .write(&path)?;
+        let [train, val, test] = dataset.public_split(&path)?;
+        assert_eq!(masked_positions(&train)?, [0, 3]);
+        assert_eq!(masked_positions(&val)?, [1]);
+        assert_eq!(masked_positions(&test)?, [2, 4]);
+
+        NpzWriter::new()
+            .add("train_idx", &Tensor::new(&[5i64], &device)?)?
+            .add("val_idx", &Tensor::new(&[1i64], &device)?)?
+            .add("test_idx", &Tensor::new(&[2i64], &device)?)?
+            .write(&path)?;
+        assert!(dataset.public_split(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_split_by_id() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("split.txt");
+        let dataset = ToyDataset::new(&[0, 1, 0, 1], &[])?;
+
+        std::fs::write(&path, "103 train\n100 train\n\n101 val\n102 test\n")?;
+        let [train, val, test] = dataset.public_split_by_id(&path)?;
+        assert_eq!(masked_positions(&train)?, [0, 3]);
+        assert_eq!(masked_positions(&val)?, [1]);
+        assert_eq!(masked_positions(&test)?, [2]);
+
+        for content in [
+            "104 train\n",
+            "100 train\n100 test\n",
+            "100 unknown\n",
+            "100\n",
+        ] {
+            std::fs::write(&path, content)?;
+            assert!(dataset.public_split_by_id(&path).is_err(), "{:?}", content);
+        }
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use anyhow::Result;
 use candle_core::Device;
 use polars::{datatypes::Float32Chunked, frame::DataFrame, series::ChunkCompare};
 
+use crate::EdgeIndex;
+
 pub trait Dataset {
     type Batch;
     type NodeSelector: Sized;
@@ -11,6 +13,12 @@ pub trait Dataset {
     fn induced_subgraph(&self, nodes: Self::NodeSelector, device: &Device) -> Result<Self::Batch>;
 }
 
+/// Batch of a homogeneous graph whose edges a loader can replace, e.g., by sampled ones.
+pub trait GraphBatch {
+    fn edge_index(&self) -> &EdgeIndex;
+    fn with_edge_index(self, edge_index: EdgeIndex) -> Self;
+}
+
 pub trait RandomSplit<Ratio> {
     type Output;
     fn random_split(&self, ratio: Ratio) -> Result<Self::Output>;
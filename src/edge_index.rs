@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+
+use candle_core::{bail, DType, Device, IndexOp, Result, Tensor};
+
+/// Edge index of a graph on `num_nodes` nodes.
+///
+/// `index` is a `(2, num_edges)` u32 tensor. Following `nn`, messages flow from
+/// `index[1]` into `index[0]`, so the degree of a node is counted on `index[0]`.
+#[derive(Debug, Clone)]
+pub struct EdgeIndex {
+    index: Tensor,
+    num_nodes: usize,
+    sorted: bool,
+    directed: bool,
+}
+impl EdgeIndex {
+    /// Validates the indices and detects whether `index[0]` is sorted and whether
+    /// the graph is directed, i.e., some edge has no reverse edge.
+    pub fn new(index: Tensor, num_nodes: usize) -> Result<Self> {
+        if index.dtype() != DType::U32 {
+            bail!("edge index must be u32, got {:?}", index.dtype())
+        }
+        if index.rank() != 2 || index.dim(0)? != 2 {
+            bail!(
+                "edge index must be of shape (2, num_edges), got {:?}",
+                index.shape()
+            )
+        }
+        let rows = index.to_vec2::<u32>()?;
+        if let Some(&i) = rows.iter().flatten().find(|&&i| i as usize >= num_nodes) {
+            bail!("edge index contains {} but num_nodes is {}", i, num_nodes)
+        }
+        let sorted = rows[0].windows(2).all(|w| w[0] <= w[1]);
+        let edges: HashSet<(u32, u32)> = rows[0]
+            .iter()
+            .cloned()
+            .zip(rows[1].iter().cloned())
+            .collect();
+        let directed = edges.iter().any(|&(u, v)| !edges.contains(&(v, u)));
+        Ok(Self {
+            index,
+            num_nodes,
+            sorted,
+            directed,
+        })
+    }
+    /// Skips the validation; the caller guarantees the indices and flags are correct.
+    pub(crate) fn new_unchecked(
+        index: Tensor,
+        num_nodes: usize,
+        sorted: bool,
+        directed: bool,
+    ) -> Self {
+        Self {
+            index,
+            num_nodes,
+            sorted,
+            directed,
+        }
+    }
+    pub fn index(&self) -> &Tensor {
+        &self.index
+    }
+    pub fn row(&self, i: usize) -> Result<Tensor> {
+        self.index.i((i, ..))
+    }
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+    pub fn num_edges(&self) -> usize {
+        self.index.dims()[1]
+    }
+    /// Whether `index[0]` is in non-decreasing order.
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+    pub fn device(&self) -> &Device {
+        self.index.device()
+    }
+    pub fn to_device(&self, device: &Device) -> Result<Self> {
+        Ok(Self {
+            index: self.index.to_device(device)?,
+            ..self.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_index() -> Result<()> {
+        let device = Device::Cpu;
+        let index = Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?;
+        let edge_index = EdgeIndex::new(index.clone(), 5)?;
+        assert_eq!(edge_index.num_nodes(), 5);
+        assert_eq!(edge_index.num_edges(), 4);
+        assert!(edge_index.is_sorted());
+        assert!(!edge_index.is_directed());
+
+        let edge_index = EdgeIndex::new(Tensor::new(&[[1u32, 0], [0, 2]], &device)?, 3)?;
+        assert!(!edge_index.is_sorted());
+        assert!(edge_index.is_directed());
+
+        assert!(EdgeIndex::new(index.clone(), 2).is_err());
+        assert!(EdgeIndex::new(index.to_dtype(DType::I64)?, 5).is_err());
+        assert!(EdgeIndex::new(index.i(0)?, 5).is_err());
+        Ok(())
+    }
+}
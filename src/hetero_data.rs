@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use candle_core::{bail, DType, Device, IndexOp, Result, Tensor};
+
+/// Heterogeneous graph with typed nodes and relations.
+///
+/// Relation `(dst, rel, src)` carries messages from `edge_index[1]` of type `src` into
+/// `edge_index[0]` of type `dst`, as in `nn::HeteroGnnModule`. Masks are u8 tensors
+/// of shape `(num_nodes,)`, as expected by `utils::mask_to_index`.
+#[derive(Debug, Clone)]
+pub struct HeteroData<NodeType, EdgeType> {
+    pub num_nodes: HashMap<NodeType, usize>,
+    pub x: HashMap<NodeType, Tensor>,
+    pub y: HashMap<NodeType, Tensor>,
+    pub train_mask: HashMap<NodeType, Tensor>,
+    pub val_mask: HashMap<NodeType, Tensor>,
+    pub test_mask: HashMap<NodeType, Tensor>,
+    pub edge_index: HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+    pub edge_attr: HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+}
+impl<NodeType, EdgeType> Default for HeteroData<NodeType, EdgeType> {
+    fn default() -> Self {
+        Self {
+            num_nodes: HashMap::new(),
+            x: HashMap::new(),
+            y: HashMap::new(),
+            train_mask: HashMap::new(),
+            val_mask: HashMap::new(),
+            test_mask: HashMap::new(),
+            edge_index: HashMap::new(),
+            edge_attr: HashMap::new(),
+        }
+    }
+}
+impl<NodeType, EdgeType> HeteroData<NodeType, EdgeType>
+where
+    NodeType: Clone + Eq + Hash,
+    EdgeType: Clone + Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node type with features of shape `(num_nodes, dim)`.
+    pub fn add_nodes(&mut self, node_type: NodeType, x: Tensor) -> Result<()> {
+        self.num_nodes.insert(node_type.clone(), x.dim(0)?);
+        self.x.insert(node_type, x);
+        Ok(())
+    }
+
+    /// Adds a relation between node types already added, validating the indices.
+    pub fn add_edges(
+        &mut self,
+        edge_type: (NodeType, EdgeType, NodeType),
+        edge_index: Tensor,
+    ) -> Result<()> {
+        let (Some(&num_dst), Some(&num_src)) = (
+            self.num_nodes.get(&edge_type.0),
+            self.num_nodes.get(&edge_type.2),
+        ) else {
+            bail!("the node types of the relation must be added first")
+        };
+        if edge_index.dtype() != DType::U32 || edge_index.rank() != 2 || edge_index.dim(0)? != 2 {
+            bail!(
+                "edge index must be u32 of shape (2, num_edges), got {:?} {:?}",
+                edge_index.dtype(),
+                edge_index.shape()
+            )
+        }
+        if edge_index.dim(1)? > 0 {
+            let max = edge_index.max(1)?.to_vec1::<u32>()?;
+            if max[0] as usize >= num_dst || max[1] as usize >= num_src {
+                bail!(
+                    "edge index contains ({}, {}) but there are ({}, {}) nodes",
+                    max[0],
+                    max[1],
+                    num_dst,
+                    num_src
+                )
+            }
+        }
+        self.edge_index.insert(edge_type, edge_index);
+        Ok(())
+    }
+
+    /// Node types and relations, sorted so that they can be used to build models
+    /// deterministically.
+    pub fn metadata(&self) -> (Vec<NodeType>, Vec<(NodeType, EdgeType, NodeType)>)
+    where
+        NodeType: Ord,
+        EdgeType: Ord,
+    {
+        let mut node_types: Vec<_> = self.num_nodes.keys().cloned().collect();
+        node_types.sort();
+        let mut edge_types: Vec<_> = self.edge_index.keys().cloned().collect();
+        edge_types.sort();
+        (node_types, edge_types)
+    }
+
+    pub fn to_device(&self, device: &Device) -> Result<Self> {
+        fn move_all<K: Clone + Eq + Hash>(
+            tensors: &HashMap<K, Tensor>,
+            device: &Device,
+        ) -> Result<HashMap<K, Tensor>> {
+            tensors
+                .iter()
+                .map(|(k, t)| Ok((k.clone(), t.to_device(device)?)))
+                .collect()
+        }
+        Ok(Self {
+            num_nodes: self.num_nodes.clone(),
+            x: move_all(&self.x, device)?,
+            y: move_all(&self.y, device)?,
+            train_mask: move_all(&self.train_mask, device)?,
+            val_mask: move_all(&self.val_mask, device)?,
+            test_mask: move_all(&self.test_mask, device)?,
+            edge_index: move_all(&self.edge_index, device)?,
+            edge_attr: move_all(&self.edge_attr, device)?,
+        })
+    }
+
+    /// Adds `(src, reverse(rel), dst)` for every relation `(dst, rel, src)`, with the
+    /// edges flipped and the edge attributes copied. Relations whose reverse is already
+    /// present, including those mapped onto themselves, are left as is.
+    pub fn add_reverse_edges<F>(&self, reverse: F) -> Result<Self>
+    where
+        F: Fn(&EdgeType) -> EdgeType,
+    {
+        let mut data = self.clone();
+        for ((dst, rel, src), edge_index) in &self.edge_index {
+            let reversed = (src.clone(), reverse(rel), dst.clone());
+            if data.edge_index.contains_key(&reversed) {
+                continue;
+            }
+            let flipped = Tensor::stack(&[edge_index.i(1)?, edge_index.i(0)?], 0)?;
+            if let Some(edge_attr) = self.edge_attr.get(&(dst.clone(), rel.clone(), src.clone())) {
+                data.edge_attr.insert(reversed.clone(), edge_attr.clone());
+            }
+            data.edge_index.insert(reversed, flipped);
+        }
+        Ok(data)
+    }
+
+    /// Keeps the given node types and the relations between them.
+    pub fn node_type_subgraph(&self, node_types: &[NodeType]) -> Self {
+        fn keep<K: Clone + Eq + Hash, V: Clone, P: Fn(&K) -> bool>(
+            map: &HashMap<K, V>,
+            predicate: P,
+        ) -> HashMap<K, V> {
+            map.iter()
+                .filter(|(k, _)| predicate(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        }
+        let is_kept = |node_type: &NodeType| node_types.contains(node_type);
+        let is_kept_edge = |edge_type: &(NodeType, EdgeType, NodeType)| {
+            is_kept(&edge_type.0) && is_kept(&edge_type.2)
+        };
+        Self {
+            num_nodes: keep(&self.num_nodes, is_kept),
+            x: keep(&self.x, is_kept),
+            y: keep(&self.y, is_kept),
+            train_mask: keep(&self.train_mask, is_kept),
+            val_mask: keep(&self.val_mask, is_kept),
+            test_mask: keep(&self.test_mask, is_kept),
+            edge_index: keep(&self.edge_index, is_kept_edge),
+            edge_attr: keep(&self.edge_attr, is_kept_edge),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_nn::{VarBuilder, VarMap};
+
+    use super::*;
+    use crate::nn::{hetero_gcn, HeteroGnnModule};
+
+    #[test]
+    fn test_hetero_data() -> Result<()> {
+        let device = Device::Cpu;
+        let mut data = HeteroData::new();
+        data.add_nodes('a', Tensor::randn(0f32, 1f32, (3, 2), &device)?)?;
+        data.add_nodes('b', Tensor::randn(0f32, 1f32, (4, 2), &device)?)?;
+        data.add_nodes('c', Tensor::randn(0f32, 1f32, (2, 2), &device)?)?;
+        data.add_edges(('a', 'x', 'b'), Tensor::new(&[[0u32, 2], [3, 1]], &device)?)?;
+        data.add_edges(('c', 'y', 'c'), Tensor::new(&[[0u32], [1]], &device)?)?;
+        data.edge_attr
+            .insert(('a', 'x', 'b'), Tensor::new(&[[1f32], [2.]], &device)?);
+        assert!(data
+            .add_edges(('b', 'x', 'a'), Tensor::new(&[[0u32], [3]], &device)?)
+            .is_err());
+        assert!(data
+            .add_edges(('a', 'x', 'd'), Tensor::new(&[[0u32], [0]], &device)?)
+            .is_err());
+
+        let data = data.add_reverse_edges(|&rel| rel)?;
+        let (node_types, edge_types) = data.metadata();
+        assert_eq!(node_types, ['a', 'b', 'c']);
+        assert_eq!(
+            edge_types,
+            [('a', 'x', 'b'), ('b', 'x', 'a'), ('c', 'y', 'c')]
+        );
+        assert_eq!(
+            data.edge_index[&('b', 'x', 'a')].to_vec2::<u32>()?,
+            [[3, 1], [0, 2]]
+        );
+        assert_eq!(
+            data.edge_attr[&('b', 'x', 'a')].to_vec2::<f32>()?,
+            [[1.], [2.]]
+        );
+
+        let subgraph = data.node_type_subgraph(&['a', 'b']);
+        let (node_types, edge_types) = subgraph.metadata();
+        assert_eq!(node_types, ['a', 'b']);
+        assert_eq!(edge_types, [('a', 'x', 'b'), ('b', 'x', 'a')]);
+
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let model = hetero_gcn(
+            &[&[('a', 2), ('b', 2)], &[('a', 3), ('b', 3)]],
+            &edge_types,
+            vs,
+        )?;
+        let output = model.forward_data(&subgraph)?;
+        let expected = model.forward(&subgraph.x, &subgraph.edge_index)?;
+        assert_eq!(
+            output[&'a'].to_vec2::<f32>()?,
+            expected[&'a'].to_vec2::<f32>()?
+        );
+        Ok(())
+    }
+}
@@ -2,6 +2,11 @@ pub mod datasets;
 pub mod nn;
 pub mod utils;
 
+mod edge_index;
+pub use edge_index::EdgeIndex;
+mod hetero_data;
+pub use hetero_data::HeteroData;
+
 #[cfg(test)]
 mod tests {
     #![allow(unused_imports)]
@@ -1,7 +1,10 @@
-use candle_core::{IndexOp, Result, Tensor, D};
-use candle_nn::{ops, Activation, Dropout, Init, Module, VarBuilder};
+use candle_core::{bail, IndexOp, Result, Tensor, D};
+use candle_nn::{ops, Activation, Dropout, Init, Linear, Module, VarBuilder};
 
-use super::traits::GnnModule;
+use super::message_passing::MessagePassing;
+use super::traits::{GnnModule, GnnModuleWithEdges};
+use super::utils::{add_self_loops, linear, linear_no_bias, remove_self_loops, segment_softmax};
+use crate::EdgeIndex;
 
 pub struct GatConv {
     in_dim: usize,
@@ -12,6 +15,8 @@ pub struct GatConv {
     weight: Tensor,
     att_src: Tensor,
     att_dst: Tensor,
+    edge_weight: Option<Tensor>,
+    att_edge: Option<Tensor>,
 }
 impl GatConv {
     pub fn new(
@@ -21,10 +26,47 @@ impl GatConv {
         negative_slope: f64,
         dropout: f32,
         vs: VarBuilder,
+    ) -> Result<Self> {
+        Self::with_edge_dim(
+            in_dim,
+            out_dim,
+            num_heads,
+            negative_slope,
+            dropout,
+            None,
+            vs,
+        )
+    }
+    /// `edge_dim` enables edge attributes of that dimension in the attention score.
+    pub fn with_edge_dim(
+        in_dim: usize,
+        out_dim: usize,
+        num_heads: usize,
+        negative_slope: f64,
+        dropout: f32,
+        edge_dim: Option<usize>,
+        vs: VarBuilder,
     ) -> Result<Self> {
         assert!(out_dim % num_heads == 0);
         let hidden_dim = out_dim / num_heads;
         let bound = (6.0 / (in_dim + out_dim) as f64).sqrt();
+        let (edge_weight, att_edge) = match edge_dim {
+            Some(edge_dim) => {
+                let bound = (6.0 / (edge_dim + out_dim) as f64).sqrt();
+                let edge_weight = vs.get_with_hints(
+                    (edge_dim, out_dim),
+                    "edge_weight",
+                    Init::Uniform {
+                        lo: -bound,
+                        up: bound,
+                    },
+                )?;
+                let att_edge =
+                    vs.get_with_hints((1, num_heads, hidden_dim), "att_edge", Init::Const(0.0))?;
+                (Some(edge_weight), Some(att_edge))
+            }
+            None => (None, None),
+        };
         Ok(Self {
             in_dim,
             out_dim,
@@ -38,50 +80,260 @@ impl GatConv {
             )?,
             att_src: vs.get_with_hints((1, num_heads, hidden_dim), "att_src", Init::Const(0.0))?,
             att_dst: vs.get_with_hints((1, num_heads, hidden_dim), "att_dst", Init::Const(0.0))?,
+            edge_weight,
+            att_edge,
             num_heads,
             negative_slope,
             dropout,
         })
     }
 }
-impl GnnModule for GatConv {
-    fn forward_t(&self, x: &Tensor, edge_index: &Tensor, _train: bool) -> Result<Tensor> {
+impl MessagePassing for GatConv {
+    fn transform(&self, x: &Tensor, _train: bool) -> Result<Tensor> {
         assert_eq!(x.shape().rank(), 2);
         assert_eq!(x.shape().dims()[1], self.in_dim);
         let hidden_dim = self.out_dim / self.num_heads;
-        let source = edge_index.i((0, ..))?;
-        let target = edge_index.i((1, ..))?;
+        x.matmul(&self.weight)?
+            .reshape(&[x.dim(0)?, self.num_heads, hidden_dim])
+    }
+    fn message(
+        &self,
+        x_i: &Tensor,
+        x_j: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        if edge_weight.is_some() {
+            bail!("GatConv does not support edge_weight")
+        }
+        // compute attention
+        let attention = {
+            let a_src = x_i.broadcast_mul(&self.att_src)?.sum_keepdim(D::Minus1)?;
+            let a_dst = x_j.broadcast_mul(&self.att_dst)?.sum_keepdim(D::Minus1)?;
+            let mut a_edge = (a_src + a_dst)?;
+            if let Some(edge_attr) = edge_attr {
+                let (Some(edge_weight), Some(att_edge)) = (&self.edge_weight, &self.att_edge)
+                else {
+                    bail!("GatConv: edge_attr is given but edge_dim is not set")
+                };
+                let e = edge_attr
+                    .matmul(edge_weight)?
+                    .reshape(x_j.shape())?
+                    .broadcast_mul(att_edge)?
+                    .sum_keepdim(D::Minus1)?;
+                a_edge = (a_edge + e)?;
+            }
+            let a_edge = ops::leaky_relu(&a_edge, self.negative_slope)?;
+            let attention = segment_softmax(&a_edge, &edge_index.row(0)?, edge_index.num_nodes())?;
+            if train && self.dropout > 0.0 {
+                ops::dropout(&attention, self.dropout)?
+            } else {
+                attention
+            }
+        };
+        x_j.broadcast_mul(&attention)
+    }
+    fn update(&self, aggr: &Tensor, _xs: &Tensor, _train: bool) -> Result<Tensor> {
+        aggr.reshape(&[aggr.dim(0)?, self.out_dim])
+    }
+}
+
+pub struct GatV2Params {
+    /// Concatenate the heads if true, average them otherwise.
+    pub concat: bool,
+    pub negative_slope: f64,
+    pub dropout_rate: f32,
+    pub add_self_loops: bool,
+    /// Dimension of the edge attributes projected into the attention score.
+    pub edge_dim: Option<usize>,
+}
+impl Default for GatV2Params {
+    fn default() -> Self {
+        Self {
+            concat: true,
+            negative_slope: 0.2,
+            dropout_rate: 0.0,
+            add_self_loops: true,
+            edge_dim: None,
+        }
+    }
+}
+
+/// https://arxiv.org/abs/2105.14491
+/// - Dynamic attention: a^T LeakyReLU(W_l x_j + W_r x_i + W_e e_ij)
+/// - `out_dim` is the output dimension, i.e., `num_heads * hidden_dim` if `concat`
+///   and `hidden_dim` otherwise.
+pub struct GatV2Conv {
+    in_dim: usize,
+    hidden_dim: usize,
+    num_heads: usize,
+    params: GatV2Params,
+    lin_l: Linear,
+    lin_r: Linear,
+    lin_edge: Option<Linear>,
+    att: Tensor,
+    bias: Tensor,
+}
+impl GatV2Conv {
+    pub fn new(in_dim: usize, out_dim: usize, num_heads: usize, vs: VarBuilder) -> Result<Self> {
+        Self::with_params(in_dim, out_dim, num_heads, GatV2Params::default(), vs)
+    }
+    pub fn with_params(
+        in_dim: usize,
+        out_dim: usize,
+        num_heads: usize,
+        params: GatV2Params,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let hidden_dim = if params.concat {
+            assert!(out_dim % num_heads == 0);
+            out_dim / num_heads
+        } else {
+            out_dim
+        };
+        let lin_edge = match params.edge_dim {
+            Some(edge_dim) => Some(linear_no_bias(
+                edge_dim,
+                num_heads * hidden_dim,
+                vs.pp("lin_edge"),
+            )?),
+            None => None,
+        };
+        let bound = (6.0 / (num_heads + hidden_dim) as f64).sqrt();
+        Ok(Self {
+            in_dim,
+            hidden_dim,
+            num_heads,
+            lin_l: linear(in_dim, num_heads * hidden_dim, vs.pp("lin_l"))?,
+            lin_r: linear(in_dim, num_heads * hidden_dim, vs.pp("lin_r"))?,
+            lin_edge,
+            att: vs.get_with_hints(
+                (1, num_heads, hidden_dim),
+                "att",
+                Init::Uniform {
+                    lo: -bound,
+                    up: bound,
+                },
+            )?,
+            bias: vs.get_with_hints((1, out_dim), "bias", Init::Const(0.0))?,
+            params,
+        })
+    }
 
-        let num_nodes = x.shape().dims()[0];
-        let h = x
-            .matmul(&self.weight)?
-            .reshape(&[num_nodes, self.num_heads, hidden_dim])?;
+    /// `edge_attr` is a `(num_edges, edge_dim)` tensor aligned with `edge_index`.
+    /// Self-loops get the mean of the attributes of the edges into the node.
+    pub fn forward_with_edge_attr(
+        &self,
+        x: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        assert_eq!(x.shape().rank(), 2);
+        assert_eq!(x.shape().dims()[1], self.in_dim);
+        if edge_attr.is_some() && self.lin_edge.is_none() {
+            bail!("GatV2Conv: edge_attr is given but edge_dim is not set")
+        }
+        let num_nodes = edge_index.num_nodes();
+        let (edge_index, edge_attr) = if self.params.add_self_loops {
+            let (edge_index, kept) = remove_self_loops(edge_index)?;
+            let edge_attr = match edge_attr {
+                Some(edge_attr) => {
+                    let edge_attr = edge_attr.i(&kept)?;
+                    let source = edge_index.row(0)?;
+                    let degree = Tensor::zeros(num_nodes, edge_attr.dtype(), x.device())?
+                        .index_add(
+                            &source,
+                            &source.ones_like()?.to_dtype(edge_attr.dtype())?,
+                            0,
+                        )?
+                        .maximum(1.0)?;
+                    let loop_attr = Tensor::zeros(
+                        (num_nodes, edge_attr.dim(1)?),
+                        edge_attr.dtype(),
+                        x.device(),
+                    )?
+                    .index_add(&source, &edge_attr, 0)?
+                    .broadcast_div(&degree.unsqueeze(1)?)?;
+                    Some(Tensor::cat(&[&edge_attr, &loop_attr], 0)?)
+                }
+                None => None,
+            };
+            (add_self_loops(&edge_index)?, edge_attr)
+        } else {
+            (edge_index.clone(), edge_attr.cloned())
+        };
+        let source = edge_index.row(0)?;
+        let target = edge_index.row(1)?;
+        let num_edges = source.dim(0)?;
+
+        let shape = (num_nodes, self.num_heads, self.hidden_dim);
+        let x_l = self.lin_l.forward(x)?.reshape(shape)?;
+        let x_r = self.lin_r.forward(x)?.reshape(shape)?;
+        let x_j = x_l.i(&target)?;
 
         // compute attention
         let attention = {
-            let a_src = h.broadcast_mul(&self.att_src)?.sum_keepdim(D::Minus1)?;
-            let a_dst = h.broadcast_mul(&self.att_dst)?.sum_keepdim(D::Minus1)?;
-            let a_edge = &ops::leaky_relu(
-                &(a_src.i(&source)? + a_dst.i(&target)?)?,
-                self.negative_slope,
-            )?
-            .exp()?;
-            let a_sum = Tensor::zeros((num_nodes, self.num_heads, 1), x.dtype(), x.device())?
-                .index_add(&source, a_edge, 0)?;
-            ops::dropout(&a_edge.broadcast_div(&a_sum.i(&source)?)?, self.dropout)
-        }?;
+            let mut e = (&x_j + x_r.i(&source)?)?;
+            if let (Some(lin_edge), Some(edge_attr)) = (&self.lin_edge, &edge_attr) {
+                let e_edge = lin_edge.forward(edge_attr)?.reshape((
+                    num_edges,
+                    self.num_heads,
+                    self.hidden_dim,
+                ))?;
+                e = (e + e_edge)?;
+            }
+            let a_edge = ops::leaky_relu(&e, self.params.negative_slope)?
+                .broadcast_mul(&self.att)?
+                .sum_keepdim(D::Minus1)?;
+            let attention = segment_softmax(&a_edge, &source, num_nodes)?;
+            if train && self.params.dropout_rate > 0.0 {
+                ops::dropout(&attention, self.params.dropout_rate)?
+            } else {
+                attention
+            }
+        };
 
-        h.zeros_like()?
-            .index_add(&source, &h.i(&target)?.broadcast_mul(&attention)?, 0)?
-            .reshape(&[num_nodes, self.out_dim])
+        let out = x_l
+            .zeros_like()?
+            .index_add(&source, &x_j.broadcast_mul(&attention)?, 0)?;
+        let out = if self.params.concat {
+            out.reshape((num_nodes, self.num_heads * self.hidden_dim))?
+        } else {
+            out.mean(1)?
+        };
+        out.broadcast_add(&self.bias)
+    }
+}
+impl GnnModule for GatV2Conv {
+    fn forward_t(&self, x: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        self.forward_with_edge_attr(x, edge_index, None, train)
+    }
+}
+impl GnnModuleWithEdges for GatV2Conv {
+    fn forward_with_edges_t(
+        &self,
+        x: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        if edge_weight.is_some() {
+            bail!("GatV2Conv does not support edge_weight")
+        }
+        self.forward_with_edge_attr(x, edge_index, edge_attr, train)
     }
 }
 
 pub struct GatParams {
-    dropout_rate: f32,
-    attention_dropout_rate: f64,
-    attention_negative_slope: f32,
-    activation_fn: Activation,
+    pub dropout_rate: f32,
+    pub attention_dropout_rate: f32,
+    pub attention_negative_slope: f64,
+    pub activation_fn: Activation,
+    pub edge_dim: Option<usize>,
 }
 impl Default for GatParams {
     fn default() -> Self {
@@ -90,6 +342,7 @@ impl Default for GatParams {
             attention_dropout_rate: 0.0,
             attention_negative_slope: 0.1,
             activation_fn: Default::default(),
+            edge_dim: None,
         }
     }
 }
@@ -112,12 +365,13 @@ impl Gat {
         let mut layers = Vec::new();
         for i in 0..sizes.len() - 1 {
             let name = format!("layer_{}", i);
-            layers.push(GatConv::new(
+            layers.push(GatConv::with_edge_dim(
                 sizes[i],
                 sizes[i + 1],
                 heads[i],
-                params.attention_dropout_rate,
                 params.attention_negative_slope,
+                params.attention_dropout_rate,
+                params.edge_dim,
                 vs.pp(name),
             )?);
         }
@@ -128,14 +382,209 @@ impl Gat {
         })
     }
 }
-impl GnnModule for Gat {
-    fn forward_t(&self, x: &Tensor, edge_index: &Tensor, train: bool) -> Result<Tensor> {
-        let mut h = self.layers[0].forward(x, edge_index)?;
+impl GnnModuleWithEdges for Gat {
+    fn forward_with_edges_t(
+        &self,
+        x: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        let mut h =
+            self.layers[0].forward_with_edges_t(x, edge_index, edge_weight, edge_attr, train)?;
         for layer in &self.layers[1..] {
             h = self.dropout.forward(&h, train)?;
             h = self.activation_fn.forward(&h)?;
-            h = layer.forward(&h, edge_index)?;
+            h = layer.forward_with_edges_t(&h, edge_index, edge_weight, edge_attr, train)?;
         }
         Ok(h)
     }
 }
+impl GnnModule for Gat {
+    fn forward_t(&self, x: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        self.forward_with_edges_t(x, edge_index, None, None, train)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{DType, Device};
+    use candle_nn::VarMap;
+
+    use super::*;
+
+    // sum of `values` weighted by the softmax of `scores`
+    fn attend(scores: &[f32], values: &[f32]) -> f32 {
+        let max = scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let exp: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+        let sum: f32 = exp.iter().sum();
+        exp.iter().zip(values).map(|(e, v)| e / sum * v).sum()
+    }
+
+    #[test]
+    fn test_gatv2_conv() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index = EdgeIndex::new(
+            Tensor::new(&[[0u32, 0, 1, 2, 3], [1, 2, 0, 0, 3]], &device)?,
+            4,
+        )?;
+        let edge_attr = Tensor::randn(0f32, 1f32, (5, 2), &device)?;
+
+        let params = GatV2Params {
+            edge_dim: Some(2),
+            ..Default::default()
+        };
+        let conv = GatV2Conv::with_params(3, 8, 2, params, vs.pp("concat"))?;
+        let ys = conv.forward_with_edge_attr(&xs, &edge_index, Some(&edge_attr), false)?;
+        assert_eq!(ys.dims(), &[4, 8]);
+
+        let params = GatV2Params {
+            concat: false,
+            ..Default::default()
+        };
+        let conv = GatV2Conv::with_params(3, 8, 2, params, vs.pp("mean"))?;
+        let ys = conv.forward(&xs, &edge_index)?;
+        assert_eq!(ys.dims(), &[4, 8]);
+        assert!(conv
+            .forward_with_edge_attr(&xs, &edge_index, Some(&edge_attr), false)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gatv2_conv_values() -> Result<()> {
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            ("lin_l.weight", Tensor::new(&[[1f32]], &device)?),
+            ("lin_l.bias", Tensor::new(&[0f32], &device)?),
+            ("lin_r.weight", Tensor::new(&[[2f32]], &device)?),
+            ("lin_r.bias", Tensor::new(&[0f32], &device)?),
+            ("lin_edge.weight", Tensor::new(&[[1f32]], &device)?),
+            ("att", Tensor::new(&[[[1f32]]], &device)?),
+            ("bias", Tensor::new(&[[0f32]], &device)?),
+        ])
+        .into_iter()
+        .map(|(name, tensor)| (name.to_string(), tensor))
+        .collect();
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let params = GatV2Params {
+            edge_dim: Some(1),
+            ..Default::default()
+        };
+        let conv = GatV2Conv::with_params(1, 1, 1, params, vs)?;
+        let xs = Tensor::new(&[[1f32], [2.], [-3.]], &device)?;
+        // node 0 receives from 1 and 2, then each node from itself
+        let edge_index = EdgeIndex::new(Tensor::new(&[[0u32, 0], [1, 2]], &device)?, 3)?;
+        let edge_attr = Tensor::new(&[[0.5f32], [-1.]], &device)?;
+
+        // score = leaky_relu(x_j + 2 x_i + e_ij) and the message is x_j
+        let ys = conv.forward(&xs, &edge_index)?;
+        let expected = [attend(&[4.0, -0.2, 3.0], &[2.0, -3.0, 1.0]), 2.0, -3.0];
+        let diff = (ys.flatten_all()? - Tensor::new(&expected, &device)?)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-6);
+
+        // the self-loop of node 0 gets the mean attribute -0.25, the others 0
+        let ys = conv.forward_with_edge_attr(&xs, &edge_index, Some(&edge_attr), false)?;
+        let expected = [attend(&[4.5, -0.4, 2.75], &[2.0, -3.0, 1.0]), 2.0, -3.0];
+        let diff = (ys.flatten_all()? - Tensor::new(&expected, &device)?)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gat_edge_attr() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?, 4)?;
+        let edge_attr = Tensor::randn(0f32, 1f32, (4, 2), &device)?;
+        let params = GatParams {
+            edge_dim: Some(2),
+            ..Default::default()
+        };
+        let model = Gat::with_params(&[3, 8, 2], &[2, 1], params, vs)?;
+        let ys = model.forward_with_edges(&xs, &edge_index, None, Some(&edge_attr))?;
+        assert_eq!(ys.dims(), &[4, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gat_conv_edge_attr_values() -> Result<()> {
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            ("weight", Tensor::new(&[[1f32]], &device)?),
+            ("att_src", Tensor::new(&[[[1f32]]], &device)?),
+            ("att_dst", Tensor::new(&[[[2f32]]], &device)?),
+            ("edge_weight", Tensor::new(&[[1f32]], &device)?),
+            ("att_edge", Tensor::new(&[[[1f32]]], &device)?),
+        ])
+        .into_iter()
+        .map(|(name, tensor)| (name.to_string(), tensor))
+        .collect();
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let conv = GatConv::with_edge_dim(1, 1, 1, 0.2, 0.0, Some(1), vs)?;
+        let xs = Tensor::new(&[[1f32], [2.], [-3.]], &device)?;
+        // node 0 receives from 1 and 2, node 1 from 0 and node 2 from none
+        let edge_index = EdgeIndex::new(Tensor::new(&[[0u32, 0, 1], [1, 2, 0]], &device)?, 3)?;
+        let edge_attr = Tensor::new(&[[0.5f32], [-1.], [2.]], &device)?;
+
+        // score = leaky_relu(x_i + 2 x_j + e_ij) and the message is x_j
+        let ys = conv.forward_with_edges(&xs, &edge_index, None, Some(&edge_attr))?;
+        let expected = [attend(&[5.5, -1.2], &[2.0, -3.0]), 1.0, 0.0];
+        let diff = (ys.flatten_all()? - Tensor::new(&expected, &device)?)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gat_train() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index = EdgeIndex::new(
+            Tensor::new(&[[0u32, 0, 0, 1, 2], [1, 2, 3, 0, 0]], &device)?,
+            4,
+        )?;
+        // only the attention dropout differs between the modes
+        let params = GatParams {
+            dropout_rate: 0.0,
+            attention_dropout_rate: 0.5,
+            ..Default::default()
+        };
+        let model = Gat::with_params(&[3, 8, 2], &[2, 1], params, vs)?;
+        let eval = model.forward_t(&xs, &edge_index, false)?;
+        assert_eq!(
+            eval.to_vec2::<f32>()?,
+            model.forward_t(&xs, &edge_index, false)?.to_vec2::<f32>()?
+        );
+        let differs = (0..10).any(|_| {
+            let train = model.forward_t(&xs, &edge_index, true).unwrap();
+            train.to_vec2::<f32>().unwrap() != eval.to_vec2::<f32>().unwrap()
+        });
+        assert!(differs);
+
+        let edge_weight = Tensor::ones(5, DType::F32, &device)?;
+        assert!(model
+            .forward_with_edges(&xs, &edge_index, Some(&edge_weight), None)
+            .is_err());
+        Ok(())
+    }
+}
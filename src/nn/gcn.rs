@@ -1,17 +1,168 @@
+use std::sync::OnceLock;
+
 use candle_core::{DType, Device, IndexOp, Result, Tensor};
 use candle_nn::{Activation, Dropout, Init, Module, VarBuilder, VarMap};
 
 use super::{
-    traits::GnnModule,
-    utils::{in_degree, out_degree, weighted_sum_agg},
+    message_passing::MessagePassing,
+    traits::{GnnModule, GnnModuleWithEdges},
+    utils::{add_self_loops, scatter_sum, weighted_sum_agg},
 };
+use crate::EdgeIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GcnNormalization {
+    /// D^-1/2 A D^-1/2
+    #[default]
+    Symmetric,
+    /// D^-1 A
+    Row,
+}
+
+/// Sparse normalized adjacency of a graph, as configured by `GcnParams`: A plus the
+/// self-loops if `add_self_loops` (of weight 2 if `improved`), then normalized by
+/// `normalization` if `normalize`, e.g., D^-1/2 (A + I) D^-1/2 with the defaults and
+/// D^-1 (A + I) with `GcnNormalization::Row`.
+///
+/// It depends on the graph only, so it can be computed once and shared by all the
+/// layers and epochs in transductive training. A `cached` conv does this itself and
+/// keeps the adjacency of its first call, ignoring any other graph it is given later.
+#[derive(Debug, Clone)]
+pub struct NormalizedAdjacency {
+    pub edge_index: EdgeIndex,
+    pub edge_weight: Tensor,
+}
+impl NormalizedAdjacency {
+    pub fn new(
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        params: &GcnParams,
+    ) -> Result<Self> {
+        GcnNorm::from(params).apply(edge_index, edge_weight)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GcnNorm {
+    add_self_loops: bool,
+    improved: bool,
+    normalize: bool,
+    normalization: GcnNormalization,
+}
+impl From<&GcnParams> for GcnNorm {
+    fn from(params: &GcnParams) -> Self {
+        Self {
+            add_self_loops: params.add_self_loops,
+            improved: params.improved,
+            normalize: params.normalize,
+            normalization: params.normalization,
+        }
+    }
+}
+// x^p with 0 for x = 0
+fn pow_nonzero(degree: &Tensor, p: f64) -> Result<Tensor> {
+    degree
+        .gt(0.0)?
+        .where_cond(&degree.maximum(1e-12)?.powf(p)?, &degree.zeros_like()?)
+}
+impl GcnNorm {
+    /// Adds the self-loops and replaces the edge weights by the normalized ones.
+    /// Existing self-loops are kept as ordinary edges.
+    fn apply(
+        &self,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+    ) -> Result<NormalizedAdjacency> {
+        let num_nodes = edge_index.num_nodes();
+        let device = edge_index.device();
+        let mut edge_weight = match edge_weight {
+            Some(edge_weight) => edge_weight.clone(),
+            None => Tensor::ones(edge_index.num_edges(), DType::F32, device)?,
+        };
+        let edge_index = if self.add_self_loops {
+            let fill_value = if self.improved { 2.0 } else { 1.0 };
+            let loop_weight = Tensor::full(fill_value, num_nodes, device)?;
+            edge_weight = Tensor::cat(
+                &[&edge_weight, &loop_weight.to_dtype(edge_weight.dtype())?],
+                0,
+            )?;
+            add_self_loops(edge_index)?
+        } else {
+            edge_index.clone()
+        };
+        if self.normalize {
+            let source = edge_index.row(0)?;
+            let degree = scatter_sum(&edge_weight, &source, num_nodes)?;
+            edge_weight = match self.normalization {
+                GcnNormalization::Symmetric => {
+                    let deg_inv_sqrt = pow_nonzero(&degree, -0.5)?;
+                    edge_weight
+                        .mul(&deg_inv_sqrt.i(&source)?)?
+                        .mul(&deg_inv_sqrt.i(&edge_index.row(1)?)?)?
+                }
+                GcnNormalization::Row => {
+                    edge_weight.mul(&pow_nonzero(&degree, -1.0)?.i(&source)?)?
+                }
+            };
+        }
+        Ok(NormalizedAdjacency {
+            edge_index,
+            edge_weight,
+        })
+    }
+}
+
+// `GcnNorm` computing the adjacency once if `cached`
+#[derive(Debug)]
+struct CachedGcnNorm {
+    norm: GcnNorm,
+    cached: bool,
+    cache: OnceLock<NormalizedAdjacency>,
+}
+impl CachedGcnNorm {
+    fn new(params: &GcnParams) -> Self {
+        Self {
+            norm: GcnNorm::from(params),
+            cached: params.cached,
+            cache: OnceLock::new(),
+        }
+    }
+    fn apply(
+        &self,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+    ) -> Result<NormalizedAdjacency> {
+        if let Some(adjacency) = self.cache.get() {
+            return Ok(adjacency.clone());
+        }
+        let adjacency = self.norm.apply(edge_index, edge_weight)?;
+        if self.cached {
+            let _ = self.cache.set(adjacency.clone());
+        }
+        Ok(adjacency)
+    }
+}
 
+/// https://arxiv.org/abs/1609.02907
+/// - out = D^-1/2 (A + I) D^-1/2 X W + b
+/// - `improved` uses A + 2I, as in the paper.
+/// - `cached` computes the normalization on the first call and reuses it afterwards,
+///   so it must be used with a fixed graph.
 pub struct GcnConv {
     weight: Tensor,
     bias: Tensor,
+    norm: CachedGcnNorm,
 }
 impl GcnConv {
     pub fn new(in_dim: usize, out_dim: usize, vs: VarBuilder) -> Result<Self> {
+        Self::with_params(in_dim, out_dim, &GcnParams::default(), vs)
+    }
+    pub fn with_params(
+        in_dim: usize,
+        out_dim: usize,
+        params: &GcnParams,
+        vs: VarBuilder,
+    ) -> Result<Self> {
         // Xavier Uniform
         let bound = (6.0 / (in_dim + out_dim) as f64).sqrt();
         let weight = vs.get_with_hints(
@@ -23,31 +174,82 @@ impl GcnConv {
             },
         )?;
         let bias = vs.get_with_hints((1, out_dim), "bias", Init::Const(0.0))?;
-        Ok(Self { weight, bias })
-    }
-}
-impl GnnModule for GcnConv {
-    fn forward_t(&self, xs: &Tensor, edge_index: &Tensor, _train: bool) -> Result<Tensor> {
-        let out_degree = out_degree(edge_index)?.maximum(1u32)?;
-        let in_degree = in_degree(edge_index)?.maximum(1u32)?;
-        let edge_weight = out_degree
-            .i(&edge_index.i((0, ..))?)?
-            .mul(&in_degree.i(&edge_index.i((1, ..))?)?)?
-            .to_dtype(xs.dtype())?
-            .powf(-0.5)?;
+        Ok(Self {
+            weight,
+            bias,
+            norm: CachedGcnNorm::new(params),
+        })
+    }
+    pub fn forward_normalized(
+        &self,
+        xs: &Tensor,
+        adjacency: &NormalizedAdjacency,
+    ) -> Result<Tensor> {
         let xs = xs.matmul(&self.weight)?;
-        weighted_sum_agg(&xs, edge_index, &edge_weight, &xs)?.broadcast_add(&self.bias)
+        weighted_sum_agg(
+            &xs,
+            adjacency.edge_index.index(),
+            &adjacency.edge_weight.to_dtype(xs.dtype())?,
+            &xs.zeros_like()?,
+        )?
+        .broadcast_add(&self.bias)
+    }
+}
+impl MessagePassing for GcnConv {
+    fn prepare_edges(
+        &self,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        _edge_attr: Option<&Tensor>,
+    ) -> Result<(EdgeIndex, Option<Tensor>, Option<Tensor>)> {
+        let adjacency = self.norm.apply(edge_index, edge_weight)?;
+        // edge_attr is unused and would not match the edges once the self-loops are added
+        Ok((adjacency.edge_index, Some(adjacency.edge_weight), None))
+    }
+    fn transform(&self, xs: &Tensor, _train: bool) -> Result<Tensor> {
+        xs.matmul(&self.weight)
+    }
+    fn message(
+        &self,
+        _x_i: &Tensor,
+        x_j: &Tensor,
+        _edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        _edge_attr: Option<&Tensor>,
+        _train: bool,
+    ) -> Result<Tensor> {
+        match edge_weight {
+            Some(edge_weight) => {
+                x_j.broadcast_mul(&edge_weight.to_dtype(x_j.dtype())?.unsqueeze(1)?)
+            }
+            None => Ok(x_j.clone()),
+        }
+    }
+    fn update(&self, aggr: &Tensor, _xs: &Tensor, _train: bool) -> Result<Tensor> {
+        aggr.broadcast_add(&self.bias)
     }
 }
 pub struct GcnParams {
     pub dropout_rate: f32,
     pub activation_fn: Activation,
+    pub add_self_loops: bool,
+    /// Self-loops of weight 2 instead of 1
+    pub improved: bool,
+    pub normalize: bool,
+    pub normalization: GcnNormalization,
+    /// Reuse the normalized adjacency of the first call, even for another graph.
+    pub cached: bool,
 }
 impl Default for GcnParams {
     fn default() -> Self {
         Self {
             dropout_rate: 0.0,
             activation_fn: Activation::Relu,
+            add_self_loops: true,
+            improved: false,
+            normalize: true,
+            normalization: GcnNormalization::Symmetric,
+            cached: false,
         }
     }
 }
@@ -55,6 +257,7 @@ pub struct Gcn {
     layers: Vec<GcnConv>,
     dropout: Dropout,
     activation_fn: Activation,
+    norm: CachedGcnNorm,
     varmap: VarMap,
 }
 impl Gcn {
@@ -64,9 +267,10 @@ impl Gcn {
 
         let mut layers = Vec::new();
         for i in 0..layer_sizes.len() - 1 {
-            layers.push(GcnConv::new(
+            layers.push(GcnConv::with_params(
                 layer_sizes[i],
                 layer_sizes[i + 1],
+                &params,
                 vs.pp(i.to_string()),
             )?);
         }
@@ -74,6 +278,7 @@ impl Gcn {
             layers,
             dropout: Dropout::new(params.dropout_rate),
             activation_fn: params.activation_fn,
+            norm: CachedGcnNorm::new(&params),
             varmap,
         })
     }
@@ -83,15 +288,178 @@ impl Gcn {
     pub fn parameters(&self) -> Vec<candle_core::Var> {
         self.varmap.all_vars()
     }
-}
-impl GnnModule for Gcn {
-    fn forward_t(&self, x: &Tensor, edge_index: &Tensor, train: bool) -> Result<Tensor> {
-        let mut h = self.layers[0].forward(x, edge_index)?;
+    /// Normalized adjacency shared by the layers; computed once if `cached`.
+    pub fn normalized_adjacency(
+        &self,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+    ) -> Result<NormalizedAdjacency> {
+        self.norm.apply(edge_index, edge_weight)
+    }
+    pub fn forward_normalized_t(
+        &self,
+        x: &Tensor,
+        adjacency: &NormalizedAdjacency,
+        train: bool,
+    ) -> Result<Tensor> {
+        let mut h = self.layers[0].forward_normalized(x, adjacency)?;
         for layer in &self.layers[1..] {
             h = self.dropout.forward(&h, train)?;
             h = self.activation_fn.forward(&h)?;
-            h = layer.forward(&h, edge_index)?;
+            h = layer.forward_normalized(&h, adjacency)?;
         }
         Ok(h)
     }
 }
+impl GnnModuleWithEdges for Gcn {
+    fn forward_with_edges_t(
+        &self,
+        x: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        _edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        let adjacency = self.normalized_adjacency(edge_index, edge_weight)?;
+        self.forward_normalized_t(x, &adjacency, train)
+    }
+}
+impl GnnModule for Gcn {
+    fn forward_t(&self, x: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        self.forward_with_edges_t(x, edge_index, None, None, train)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // D^-1/2 (A + fill I) D^-1/2 X W + b computed densely
+    fn dense_gcn(
+        adjacency: &Tensor,
+        xs: &Tensor,
+        weight: &Tensor,
+        fill_value: f64,
+        normalization: GcnNormalization,
+    ) -> Result<Tensor> {
+        let num_nodes = adjacency.dim(0)?;
+        let eye = Tensor::eye(num_nodes, DType::F32, adjacency.device())?;
+        let a = (adjacency + eye.affine(fill_value, 0.0)?)?;
+        let degree = a.sum_keepdim(1)?;
+        let a = match normalization {
+            GcnNormalization::Symmetric => {
+                let d = degree.powf(-0.5)?;
+                a.broadcast_mul(&d)?.broadcast_mul(&d.t()?)?
+            }
+            GcnNormalization::Row => a.broadcast_div(&degree)?,
+        };
+        a.matmul(xs)?.matmul(weight)
+    }
+
+    #[test]
+    fn test_gcn_normalization() -> Result<()> {
+        let device = Device::Cpu;
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let weight = Tensor::randn(0f32, 1f32, (3, 2), &device)?;
+        let tensors = HashMap::from([
+            ("weight".to_string(), weight.clone()),
+            (
+                "bias".to_string(),
+                Tensor::zeros((1, 2), DType::F32, &device)?,
+            ),
+        ]);
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+
+        // weighted path 0 - 1 - 2 and an isolated node 3
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 1, 1, 2], [1, 0, 2, 1]], &device)?, 4)?;
+        let edge_weight = Tensor::new(&[1f32, 1., 3., 3.], &device)?;
+        let adjacency = Tensor::new(
+            &[
+                [0f32, 1., 0., 0.],
+                [1., 0., 3., 0.],
+                [0., 3., 0., 0.],
+                [0., 0., 0., 0.],
+            ],
+            &device,
+        )?;
+
+        for (improved, normalization) in [
+            (false, GcnNormalization::Symmetric),
+            (true, GcnNormalization::Symmetric),
+            (false, GcnNormalization::Row),
+        ] {
+            let params = GcnParams {
+                improved,
+                normalization,
+                ..Default::default()
+            };
+            let conv = GcnConv::with_params(3, 2, &params, vs.clone())?;
+            let fill_value = if improved { 2.0 } else { 1.0 };
+            let expected = dense_gcn(&adjacency, &xs, &weight, fill_value, normalization)?;
+            let actual = conv.forward_with_edges(&xs, &edge_index, Some(&edge_weight), None)?;
+            let diff = (actual - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert!(diff < 1e-5);
+        }
+
+        let params = GcnParams {
+            add_self_loops: false,
+            normalize: false,
+            ..Default::default()
+        };
+        let conv = GcnConv::with_params(3, 2, &params, vs)?;
+        let expected = adjacency.matmul(&xs)?.matmul(&weight)?;
+        let actual = conv.forward_with_edges(&xs, &edge_index, Some(&edge_weight), None)?;
+        let diff = (actual - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gcn_cached() -> Result<()> {
+        let device = Device::Cpu;
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 1, 1, 2], [1, 0, 2, 1]], &device)?, 4)?;
+        let params = GcnParams {
+            cached: true,
+            ..Default::default()
+        };
+        let model = Gcn::with_params(&[3, 4, 2], params, &device)?;
+        let adjacency = NormalizedAdjacency::new(&edge_index, None, &GcnParams::default())?;
+        let expected = model.forward_normalized_t(&xs, &adjacency, false)?;
+        let actual = model.forward(&xs, &edge_index)?;
+        let diff = (&actual - &expected)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+
+        // the cached normalization is reused for a different graph
+        let other = EdgeIndex::new(Tensor::new(&[[0u32], [1]], &device)?, 4)?;
+        let actual = model.forward(&xs, &other)?;
+        let diff = (actual - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gcn_conv_edge_attr() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 1, 1, 2], [1, 0, 2, 1]], &device)?, 4)?;
+        let edge_attr = Tensor::randn(0f32, 1f32, (4, 2), &device)?;
+        let conv = GcnConv::new(3, 2, vs)?;
+        let (edge_index, edge_weight, edge_attr) =
+            conv.prepare_edges(&edge_index, None, Some(&edge_attr))?;
+        // the self-loops are appended to the edges and their weights
+        assert_eq!(edge_index.num_edges(), 8);
+        assert_eq!(edge_weight.map(|w| w.dims().to_vec()), Some(vec![8]));
+        assert!(edge_attr.is_none());
+        Ok(())
+    }
+}
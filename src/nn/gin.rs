@@ -1,11 +1,13 @@
-use candle_core::{Result, Tensor};
+use candle_core::{bail, Result, Tensor};
 use candle_nn::{
-    batch_norm, Activation, BatchNorm, BatchNormConfig, Dropout, Linear, Module, ModuleT,
+    batch_norm, Activation, BatchNorm, BatchNormConfig, Dropout, Init, Linear, Module, ModuleT,
     VarBuilder,
 };
 
+use super::message_passing::MessagePassing;
 use super::traits::GnnModule;
-use super::utils::{linear, sum_agg};
+use super::utils::linear;
+use crate::EdgeIndex;
 
 struct Mlp {
     fc1: Linear,
@@ -37,40 +39,132 @@ impl ModuleT for Mlp {
         let xs = self.fc1.forward(xs)?;
         let xs = self.activation_fn.forward(&xs)?;
         let xs = self.dropout.forward(&xs, train)?;
-        let xs = self.normalization_fn.forward(&xs)?;
+        let xs = self.normalization_fn.forward_t(&xs, train)?;
         let xs = self.fc2.forward(&xs)?;
         Ok(xs)
     }
 }
+/// https://arxiv.org/abs/1810.00826
+/// - out = nn((1 + eps) x_i + sum_j x_j)
+/// - GIN-0 fixes eps = 0 and GIN-eps learns it (`train_eps`).
 pub struct GinConv {
     nn: Box<dyn ModuleT>,
+    eps: Tensor,
 }
 impl GinConv {
-    pub fn new(nn: Box<dyn ModuleT>, _vs: VarBuilder) -> Result<Self> {
-        Ok(Self { nn })
+    pub fn new(nn: Box<dyn ModuleT>, vs: VarBuilder) -> Result<Self> {
+        Self::with_eps(nn, 0.0, false, vs)
+    }
+    pub fn with_mlp<M: ModuleT + 'static>(
+        nn: M,
+        eps: f64,
+        train_eps: bool,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        Self::with_eps(Box::new(nn), eps, train_eps, vs)
+    }
+    fn with_eps(nn: Box<dyn ModuleT>, eps: f64, train_eps: bool, vs: VarBuilder) -> Result<Self> {
+        let eps = if train_eps {
+            vs.get_with_hints(1, "eps", Init::Const(eps))?
+        } else {
+            Tensor::full(eps, 1, vs.device())?.to_dtype(vs.dtype())?
+        };
+        Ok(Self { nn, eps })
     }
 }
-impl GnnModule for GinConv {
-    fn forward_t(&self, xs: &Tensor, edge_index: &Tensor, train: bool) -> Result<Tensor> {
-        let out = sum_agg(xs, edge_index, xs)?;
-        self.nn.forward_t(&out, train)
+impl MessagePassing for GinConv {
+    fn message(
+        &self,
+        _x_i: &Tensor,
+        x_j: &Tensor,
+        _edge_index: &EdgeIndex,
+        _edge_weight: Option<&Tensor>,
+        _edge_attr: Option<&Tensor>,
+        _train: bool,
+    ) -> Result<Tensor> {
+        Ok(x_j.clone())
+    }
+    fn update(&self, aggr: &Tensor, xs: &Tensor, train: bool) -> Result<Tensor> {
+        let root = xs.broadcast_mul(&(&self.eps + 1.0)?.to_dtype(xs.dtype())?)?;
+        self.nn.forward_t(&(aggr + root)?, train)
+    }
+}
+
+/// https://arxiv.org/abs/1905.12265
+/// - out = nn(x_i + sum_j ReLU(x_j + W e_ij))
+/// - Without `edge_dim`, the edge attributes must have the dimension of the input.
+pub struct GineConv {
+    nn: Box<dyn ModuleT>,
+    lin_edge: Option<Linear>,
+}
+impl GineConv {
+    pub fn new(
+        nn: Box<dyn ModuleT>,
+        in_dim: usize,
+        edge_dim: Option<usize>,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let lin_edge = match edge_dim {
+            Some(edge_dim) => Some(linear(edge_dim, in_dim, vs.pp("lin_edge"))?),
+            None => None,
+        };
+        Ok(Self { nn, lin_edge })
+    }
+}
+impl MessagePassing for GineConv {
+    fn message(
+        &self,
+        _x_i: &Tensor,
+        x_j: &Tensor,
+        _edge_index: &EdgeIndex,
+        _edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        _train: bool,
+    ) -> Result<Tensor> {
+        let Some(edge_attr) = edge_attr else {
+            bail!("GineConv requires edge_attr")
+        };
+        let edge_attr = match &self.lin_edge {
+            Some(lin_edge) => lin_edge.forward(edge_attr)?,
+            None => edge_attr.clone(),
+        };
+        (x_j + edge_attr)?.relu()
+    }
+    fn update(&self, aggr: &Tensor, xs: &Tensor, train: bool) -> Result<Tensor> {
+        self.nn.forward_t(&(aggr + xs)?, train)
     }
 }
 
 pub struct GinParams {
-    activation_fn: Activation,
-    dropout_rate: f32,
+    pub activation_fn: Activation,
+    pub dropout_rate: f32,
+    pub eps: f64,
+    pub train_eps: bool,
+    /// Applied between the layers, after each layer's MLP.
+    pub inter_layer_activation_fn: Option<Activation>,
+    pub inter_layer_dropout_rate: f32,
+    /// Concatenates the outputs of all layers (jumping knowledge) instead of
+    /// returning the last one, so the output dimension is `sizes[1..].sum()`.
+    pub jumping_knowledge: bool,
 }
 impl Default for GinParams {
     fn default() -> Self {
         Self {
             activation_fn: Activation::Relu,
             dropout_rate: 0.5,
+            eps: 0.0,
+            train_eps: false,
+            inter_layer_activation_fn: None,
+            inter_layer_dropout_rate: 0.0,
+            jumping_knowledge: false,
         }
     }
 }
 pub struct Gin {
     layers: Vec<GinConv>,
+    activation_fn: Option<Activation>,
+    dropout: Dropout,
+    jumping_knowledge: bool,
 }
 impl Gin {
     pub fn new(sizes: &[usize], vs: VarBuilder) -> Result<Self> {
@@ -89,17 +183,115 @@ impl Gin {
                 params.dropout_rate,
                 vs_sub.pp("mlp"),
             )?;
-            layers.push(GinConv::new(Box::new(mlp), vs_sub)?);
+            layers.push(GinConv::with_mlp(
+                mlp,
+                params.eps,
+                params.train_eps,
+                vs_sub,
+            )?);
         }
-        Ok(Self { layers })
+        Ok(Self {
+            layers,
+            activation_fn: params.inter_layer_activation_fn,
+            dropout: Dropout::new(params.inter_layer_dropout_rate),
+            jumping_knowledge: params.jumping_knowledge,
+        })
     }
 }
 impl GnnModule for Gin {
-    fn forward_t(&self, x: &Tensor, edge_index: &Tensor, _train: bool) -> Result<Tensor> {
-        let mut h = x.clone();
-        for layer in &self.layers {
-            h = layer.forward(&h, edge_index)?;
+    fn forward_t(&self, x: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        let mut h = self.layers[0].forward_t(x, edge_index, train)?;
+        let mut hs = vec![h.clone()];
+        for layer in &self.layers[1..] {
+            if let Some(activation_fn) = &self.activation_fn {
+                h = activation_fn.forward(&h)?;
+            }
+            h = self.dropout.forward(&h, train)?;
+            h = layer.forward_t(&h, edge_index, train)?;
+            hs.push(h.clone());
+        }
+        if self.jumping_knowledge {
+            Tensor::cat(&hs, 1)
+        } else {
+            Ok(h)
         }
-        Ok(h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device};
+    use candle_nn::VarMap;
+
+    use super::*;
+    use crate::nn::GnnModuleWithEdges;
+
+    #[test]
+    fn test_gin_eps() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::new(&[[1f32], [2.], [4.]], &device)?;
+        let edge_index = EdgeIndex::new(Tensor::new(&[[0u32, 0, 1], [1, 2, 0]], &device)?, 3)?;
+        let identity = candle_nn::func(|xs| Ok(xs.clone()));
+
+        let conv = GinConv::with_mlp(identity.clone(), 0.5, false, vs.pp("fixed"))?;
+        let ys = conv.forward(&xs, &edge_index)?;
+        assert_eq!(ys.to_vec2::<f32>()?, [[7.5], [4.], [6.]]);
+        assert!(varmap.data().lock().unwrap().is_empty());
+
+        let conv = GinConv::with_mlp(identity, 0.5, true, vs.pp("trained"))?;
+        let ys = conv.forward(&xs, &edge_index)?;
+        assert_eq!(ys.to_vec2::<f32>()?, [[7.5], [4.], [6.]]);
+        assert_eq!(varmap.all_vars().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gin_train() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?, 4)?;
+        let params = GinParams {
+            inter_layer_activation_fn: Some(Activation::Relu),
+            // without dropout only BatchNorm differs between train and eval
+            dropout_rate: 0.0,
+            inter_layer_dropout_rate: 0.0,
+            jumping_knowledge: true,
+            ..Default::default()
+        };
+        let gin = Gin::with_params(&[3, 8, 5], params, vs)?;
+        let ys = gin.forward_t(&xs, &edge_index, false)?;
+        assert_eq!(ys.dims(), &[4, 13]);
+        assert_eq!(
+            ys.to_vec2::<f32>()?,
+            gin.forward_t(&xs, &edge_index, false)?.to_vec2::<f32>()?
+        );
+
+        // batch statistics differ from the initial running statistics
+        let ys_train = gin.forward_t(&xs, &edge_index, true)?;
+        let diff = (ys_train - ys)?.abs()?.sum_all()?.to_scalar::<f32>()?;
+        assert!(diff > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gine_conv() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?, 4)?;
+        let edge_attr = Tensor::randn(0f32, 1f32, (4, 2), &device)?;
+        let nn = linear(3, 5, vs.pp("nn"))?;
+        let conv = GineConv::new(Box::new(nn), 3, Some(2), vs.pp("conv"))?;
+        let ys = conv.forward_with_edges(&xs, &edge_index, None, Some(&edge_attr))?;
+        assert_eq!(ys.dims(), &[4, 5]);
+        assert!(conv.forward(&xs, &edge_index).is_err());
+        Ok(())
     }
 }
@@ -1,20 +1,86 @@
 use std::collections::HashMap;
 use std::hash::Hash;
 
-use candle_core::{Result, Tensor};
-use candle_nn::{Activation, Dropout, Init, Module, VarBuilder};
+use candle_core::{IndexOp, Result, Tensor};
+use candle_nn::{ops::softmax, Activation, Dropout, Init, Linear, Module, VarBuilder};
 
 use super::{
-    utils::{apply, mean_agg},
+    message_passing::Aggregation,
+    to_hetero::HeteroAggregation,
+    utils::{apply, linear},
     HeteroGnnModule,
 };
 
+/// Reduction over the relations into a node type of `HeteroGcnConv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeteroGcnAggregation {
+    Reduce(HeteroAggregation),
+    /// Semantic attention over the relations (https://arxiv.org/abs/1903.07293),
+    /// with learned parameters per node type.
+    Attention,
+}
+impl Default for HeteroGcnAggregation {
+    fn default() -> Self {
+        Self::Reduce(HeteroAggregation::default())
+    }
+}
+impl From<HeteroAggregation> for HeteroGcnAggregation {
+    fn from(aggregation: HeteroAggregation) -> Self {
+        Self::Reduce(aggregation)
+    }
+}
+
+/// Semantic attention over the relations into a node type
+/// - w_r = mean_i q^T tanh(W z_ri + b)
+/// - out = sum_r softmax(w)_r z_r
+struct RelationAttention {
+    lin: Linear,
+    q: Tensor,
+}
+impl RelationAttention {
+    fn new(dim: usize, vs: VarBuilder) -> Result<Self> {
+        let bound = (6.0 / (dim + 1) as f64).sqrt();
+        Ok(Self {
+            lin: linear(dim, dim, vs.clone())?,
+            q: vs.get_with_hints(
+                (dim, 1),
+                "q",
+                Init::Uniform {
+                    lo: -bound,
+                    up: bound,
+                },
+            )?,
+        })
+    }
+    fn reduce(&self, outputs: &[Tensor]) -> Result<Tensor> {
+        // (num_relations, num_nodes, dim)
+        let stacked = Tensor::stack(outputs, 0)?;
+        let scores = self
+            .lin
+            .forward(&stacked)?
+            .tanh()?
+            .broadcast_matmul(&self.q)?
+            .mean((1, 2))?;
+        let beta = softmax(&scores, 0)?.reshape(((), 1, 1))?;
+        stacked.broadcast_mul(&beta)?.sum(0)
+    }
+}
+
 /// https://arxiv.org/pdf/1703.06103.pdf
 /// - Relation-Based Transformation
 /// - Root is also transformed
+/// - out = x_i W_root + reduce_r(agg_{j in N_r(i)} x_j W_r) + b, where `agg` is
+///   `aggregation` within each relation and `reduce` is `group_aggregation` over the
+///   relations into a node type, applied in the order of `edge_types`
 pub struct HeteroGcnConv<NodeType, EdgeType> {
+    out_dims: HashMap<NodeType, usize>,
     node_ws: HashMap<NodeType, Tensor>,
-    edge_ws: HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+    biases: HashMap<NodeType, Tensor>,
+    edge_types: Vec<(NodeType, EdgeType, NodeType)>,
+    edge_ws: Vec<Tensor>,
+    aggregation: Aggregation,
+    group_aggregation: HeteroGcnAggregation,
+    attention: HashMap<NodeType, RelationAttention>,
 }
 impl<NodeType, EdgeType> HeteroGcnConv<NodeType, EdgeType>
 where
@@ -26,26 +92,71 @@ where
         out_dims: &[(NodeType, usize)],
         edge_types: &[(NodeType, EdgeType, NodeType)],
         vs: VarBuilder,
+    ) -> Result<Self> {
+        Self::with_params(
+            in_dims,
+            out_dims,
+            edge_types,
+            &HeteroGcnParams::default(),
+            vs,
+        )
+    }
+    pub fn with_aggregation(
+        in_dims: &[(NodeType, usize)],
+        out_dims: &[(NodeType, usize)],
+        edge_types: &[(NodeType, EdgeType, NodeType)],
+        group_aggregation: HeteroGcnAggregation,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let params = HeteroGcnParams {
+            group_aggregation,
+            ..Default::default()
+        };
+        Self::with_params(in_dims, out_dims, edge_types, &params, vs)
+    }
+    pub fn with_params(
+        in_dims: &[(NodeType, usize)],
+        out_dims: &[(NodeType, usize)],
+        edge_types: &[(NodeType, EdgeType, NodeType)],
+        params: &HeteroGcnParams,
+        vs: VarBuilder,
     ) -> Result<Self> {
         let in_dims: HashMap<NodeType, usize> = in_dims.iter().cloned().collect();
         let out_dims: HashMap<NodeType, usize> = out_dims.iter().cloned().collect();
 
         let mut node_ws = HashMap::new();
+        let mut biases = HashMap::new();
+        let mut attention = HashMap::new();
         for (node_type, &out_dim) in &out_dims {
             let in_dim = in_dims[node_type];
 
-            let bound = (6.0 / (in_dim + out_dim) as f64).sqrt();
-            let weight = vs.get_with_hints(
-                (in_dim, out_dim),
-                &format!("weight[{}]", node_type.to_string()),
-                Init::Uniform {
-                    lo: -bound,
-                    up: bound,
-                },
-            )?;
-            node_ws.insert(node_type.clone(), weight);
+            if params.root_weight {
+                let bound = (6.0 / (in_dim + out_dim) as f64).sqrt();
+                let weight = vs.get_with_hints(
+                    (in_dim, out_dim),
+                    &format!("weight[{}]", node_type.to_string()),
+                    Init::Uniform {
+                        lo: -bound,
+                        up: bound,
+                    },
+                )?;
+                node_ws.insert(node_type.clone(), weight);
+            }
+            if params.bias {
+                let bias = vs.get_with_hints(
+                    (1, out_dim),
+                    &format!("bias[{}]", node_type.to_string()),
+                    Init::Const(0.0),
+                )?;
+                biases.insert(node_type.clone(), bias);
+            }
+            if params.group_aggregation == HeteroGcnAggregation::Attention {
+                let vs = vs.pp(format!("attention[{}]", node_type.to_string()));
+                attention.insert(node_type.clone(), RelationAttention::new(out_dim, vs)?);
+            }
         }
-        let mut edge_ws = HashMap::new();
+        let mut valid_edge_types = Vec::new();
+        let mut edge_ws = Vec::new();
         for edge_type in edge_types.iter() {
             if !in_dims.contains_key(&edge_type.2) || !out_dims.contains_key(&edge_type.0) {
                 continue;
@@ -67,9 +178,19 @@ where
                     up: bound,
                 },
             )?;
-            edge_ws.insert(edge_type.clone(), weight);
+            valid_edge_types.push(edge_type.clone());
+            edge_ws.push(weight);
         }
-        Ok(Self { node_ws, edge_ws })
+        Ok(Self {
+            out_dims,
+            node_ws,
+            biases,
+            edge_types: valid_edge_types,
+            edge_ws,
+            aggregation: params.aggregation,
+            group_aggregation: params.group_aggregation,
+            attention,
+        })
     }
 
     pub fn forward(
@@ -77,32 +198,113 @@ where
         xs: &HashMap<NodeType, Tensor>,
         edge_index: &HashMap<(NodeType, EdgeType, NodeType), Tensor>,
     ) -> Result<HashMap<NodeType, Tensor>> {
-        let mut output: HashMap<NodeType, Tensor> = self
-            .node_ws
-            .iter()
-            .map(|(node_type, ws)| xs[node_type].matmul(ws).map(|x| (node_type.clone(), x)))
-            .collect::<Result<_>>()?;
-        for (edge_type, ws) in &self.edge_ws {
-            let edge_index = &edge_index[edge_type];
-            output.insert(
-                edge_type.0.clone(),
-                mean_agg(
-                    &xs[&edge_type.2].matmul(ws)?,
-                    edge_index,
-                    &output[&edge_type.0],
-                )?,
-            );
+        let mut grouped: HashMap<NodeType, Vec<Tensor>> = HashMap::new();
+        for (edge_type, ws) in self.edge_types.iter().zip(&self.edge_ws) {
+            let Some(edge_index) = edge_index.get(edge_type) else {
+                continue;
+            };
+            let messages = xs[&edge_type.2].matmul(ws)?.i(&edge_index.i((1, ..))?)?;
+            let aggr = self.aggregation.aggregate(
+                &messages,
+                &edge_index.i((0, ..))?,
+                xs[&edge_type.0].dim(0)?,
+            )?;
+            grouped.entry(edge_type.0.clone()).or_default().push(aggr);
         }
-        Ok(output)
+        self.out_dims
+            .iter()
+            .map(|(node_type, &out_dim)| {
+                let x = &xs[node_type];
+                let mut output = match self.node_ws.get(node_type) {
+                    Some(ws) => x.matmul(ws)?,
+                    None => Tensor::zeros((x.dim(0)?, out_dim), x.dtype(), x.device())?,
+                };
+                if let Some(outputs) = grouped.get(node_type) {
+                    let aggr = match self.group_aggregation {
+                        HeteroGcnAggregation::Reduce(aggregation) => aggregation.reduce(outputs)?,
+                        HeteroGcnAggregation::Attention => {
+                            self.attention[node_type].reduce(outputs)?
+                        }
+                    };
+                    output = (output + aggr)?;
+                }
+                if let Some(bias) = self.biases.get(node_type) {
+                    output = output.broadcast_add(bias)?;
+                }
+                Ok((node_type.clone(), output))
+            })
+            .collect()
     }
 }
 
+pub struct HeteroGcnParams {
+    pub dropout_rate: f32,
+    pub activation_fn: Activation,
+    /// Apply the activation and dropout before the first layer too.
+    pub first_activation: bool,
+    /// Aggregation of the neighbours within a relation
+    pub aggregation: Aggregation,
+    /// Reduction over the relations into a node type
+    pub group_aggregation: HeteroGcnAggregation,
+    pub root_weight: bool,
+    pub bias: bool,
+}
+impl Default for HeteroGcnParams {
+    fn default() -> Self {
+        Self {
+            dropout_rate: 0.1,
+            activation_fn: Activation::default(),
+            first_activation: false,
+            aggregation: Aggregation::Mean,
+            group_aggregation: HeteroGcnAggregation::default(),
+            root_weight: true,
+            bias: false,
+        }
+    }
+}
 pub struct HeteroGcn<NodeType, EdgeType> {
     layers: Vec<HeteroGcnConv<NodeType, EdgeType>>,
     activation_fn: Activation,
     dropout: Dropout,
     first_activation: bool,
 }
+impl<NodeType, EdgeType> HeteroGcn<NodeType, EdgeType>
+where
+    NodeType: Clone + Eq + Hash + ToString,
+    EdgeType: Clone + Eq + Hash + ToString,
+{
+    pub fn new(
+        layer_sizes: &[&[(NodeType, usize)]],
+        edge_types: &[(NodeType, EdgeType, NodeType)],
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        Self::with_params(layer_sizes, edge_types, HeteroGcnParams::default(), vs)
+    }
+    pub fn with_params(
+        layer_sizes: &[&[(NodeType, usize)]],
+        edge_types: &[(NodeType, EdgeType, NodeType)],
+        params: HeteroGcnParams,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let num_layers = layer_sizes.len();
+        let mut layers = Vec::new();
+        for i in 1..num_layers {
+            layers.push(HeteroGcnConv::with_params(
+                layer_sizes[i - 1],
+                layer_sizes[i],
+                edge_types,
+                &params,
+                vs.pp(i.to_string()),
+            )?);
+        }
+        Ok(Self {
+            layers,
+            activation_fn: params.activation_fn,
+            dropout: Dropout::new(params.dropout_rate),
+            first_activation: params.first_activation,
+        })
+    }
+}
 impl<NodeType, EdgeType> HeteroGnnModule<NodeType, EdgeType> for HeteroGcn<NodeType, EdgeType>
 where
     NodeType: Clone + Eq + Hash + ToString,
@@ -138,22 +340,7 @@ where
     NodeType: Clone + Eq + Hash + ToString,
     EdgeType: Clone + Eq + Hash + ToString,
 {
-    let num_layers = layer_sizes.len();
-    let mut layers = Vec::new();
-    for i in 1..num_layers {
-        layers.push(HeteroGcnConv::new(
-            layer_sizes[i - 1],
-            layer_sizes[i],
-            edge_types,
-            vs.pp(i.to_string()),
-        )?);
-    }
-    Ok(HeteroGcn {
-        layers,
-        activation_fn: Activation::default(),
-        dropout: Dropout::new(0.1),
-        first_activation: false,
-    })
+    HeteroGcn::new(layer_sizes, edge_types, vs)
 }
 
 #[cfg(test)]
@@ -192,4 +379,140 @@ mod tests {
         assert_eq!(output[&'b'].shape().dims(), &[6, 4]);
         Ok(())
     }
+
+    #[test]
+    fn test_hetero_gcn_aggregation() -> Result<()> {
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            ("weight[a]".to_string(), Tensor::new(&[[1f32]], &device)?),
+            ("weight[b]".to_string(), Tensor::new(&[[1f32]], &device)?),
+            (
+                "weight[a,x,b]".to_string(),
+                Tensor::new(&[[2f32]], &device)?,
+            ),
+            (
+                "weight[a,y,a]".to_string(),
+                Tensor::new(&[[3f32]], &device)?,
+            ),
+            (
+                "attention[a].weight".to_string(),
+                Tensor::new(&[[0.1f32]], &device)?,
+            ),
+            (
+                "attention[a].bias".to_string(),
+                Tensor::new(&[0f32], &device)?,
+            ),
+            (
+                "attention[a].q".to_string(),
+                Tensor::new(&[[1f32]], &device)?,
+            ),
+            (
+                "attention[b].weight".to_string(),
+                Tensor::new(&[[0f32]], &device)?,
+            ),
+            (
+                "attention[b].bias".to_string(),
+                Tensor::new(&[0f32], &device)?,
+            ),
+            (
+                "attention[b].q".to_string(),
+                Tensor::new(&[[0f32]], &device)?,
+            ),
+        ]);
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let xs = HashMap::from([
+            ('a', Tensor::new(&[[1f32], [2.], [4.]], &device)?),
+            ('b', Tensor::new(&[[10f32], [20.]], &device)?),
+        ]);
+        let edge_index = HashMap::from([
+            (
+                ('a', 'x', 'b'),
+                Tensor::new(&[[0u32, 0, 1], [0, 1, 1]], &device)?,
+            ),
+            (('a', 'y', 'a'), Tensor::new(&[[0u32, 2], [1, 1]], &device)?),
+        ]);
+        // root: [1, 2, 4], relation x: [30, 40, 0], relation y: [6, 0, 6]
+        let beta_x = {
+            let w_x = [3f32, 4., 0.].map(|v| v.tanh()).iter().sum::<f32>() / 3.;
+            let w_y = [0.6f32, 0., 0.6].map(|v| v.tanh()).iter().sum::<f32>() / 3.;
+            1. / (1. + (w_y - w_x).exp())
+        };
+        let attended = |root: f32, x: f32, y: f32| root + beta_x * x + (1. - beta_x) * y;
+        for (aggregation, expected) in [
+            (HeteroAggregation::Sum.into(), [37f32, 42., 10.]),
+            (HeteroAggregation::Mean.into(), [19., 22., 7.]),
+            (HeteroAggregation::Max.into(), [31., 42., 10.]),
+            (
+                HeteroGcnAggregation::Attention,
+                [
+                    attended(1., 30., 6.),
+                    attended(2., 40., 0.),
+                    attended(4., 0., 6.),
+                ],
+            ),
+        ] {
+            let conv = HeteroGcnConv::with_aggregation(
+                &[('a', 1), ('b', 1)],
+                &[('a', 1), ('b', 1)],
+                &[('a', 'x', 'b'), ('a', 'y', 'a')],
+                aggregation,
+                vs.clone(),
+            )?;
+            let output = conv.forward(&xs, &edge_index)?;
+            let a = output[&'a'].flatten_all()?.to_vec1::<f32>()?;
+            for (a, e) in a.iter().zip(expected) {
+                assert!((a - e).abs() < 1e-5, "{:?}: {} != {}", aggregation, a, e);
+            }
+            assert_eq!(output[&'b'].to_vec2::<f32>()?, [[10.], [20.]]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hetero_gcn_params() -> Result<()> {
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            (
+                "weight[a,x,b]".to_string(),
+                Tensor::new(&[[2f32]], &device)?,
+            ),
+            (
+                "weight[a,y,a]".to_string(),
+                Tensor::new(&[[3f32]], &device)?,
+            ),
+            ("bias[a]".to_string(), Tensor::new(&[[0.5f32]], &device)?),
+            ("bias[b]".to_string(), Tensor::new(&[[-1f32]], &device)?),
+        ]);
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let params = HeteroGcnParams {
+            aggregation: Aggregation::Sum,
+            group_aggregation: HeteroAggregation::Max.into(),
+            root_weight: false,
+            bias: true,
+            ..Default::default()
+        };
+        let conv = HeteroGcnConv::with_params(
+            &[('a', 1), ('b', 1)],
+            &[('a', 1), ('b', 1)],
+            &[('a', 'x', 'b'), ('a', 'y', 'a')],
+            &params,
+            vs,
+        )?;
+        let xs = HashMap::from([
+            ('a', Tensor::new(&[[1f32], [2.], [4.]], &device)?),
+            ('b', Tensor::new(&[[10f32], [20.]], &device)?),
+        ]);
+        let edge_index = HashMap::from([
+            (
+                ('a', 'x', 'b'),
+                Tensor::new(&[[0u32, 0, 1], [0, 1, 1]], &device)?,
+            ),
+            (('a', 'y', 'a'), Tensor::new(&[[0u32, 2], [1, 1]], &device)?),
+        ]);
+        // relation x: [60, 40, 0], relation y: [6, 0, 6]
+        let output = conv.forward(&xs, &edge_index)?;
+        assert_eq!(output[&'a'].to_vec2::<f32>()?, [[60.5], [40.5], [6.5]]);
+        assert_eq!(output[&'b'].to_vec2::<f32>()?, [[-1.], [-1.]]);
+        Ok(())
+    }
 }
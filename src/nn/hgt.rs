@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use candle_core::{bail, IndexOp, Result, Tensor};
+use candle_nn::{Init, Linear, Module, VarBuilder};
+
+use super::{
+    utils::{linear, scatter_sum, segment_softmax},
+    HeteroGnnModule,
+};
+
+struct HgtRelation {
+    // (num_heads, head_dim, head_dim)
+    att: Tensor,
+    msg: Tensor,
+    // (1, num_heads)
+    prior: Tensor,
+}
+
+struct HgtNode {
+    k_lin: Linear,
+    q_lin: Linear,
+    v_lin: Linear,
+    a_lin: Linear,
+    skip: Option<Tensor>,
+}
+
+// scores, messages and targets of the incoming edges of a node type
+#[derive(Default)]
+struct Incoming {
+    scores: Vec<Tensor>,
+    messages: Vec<Tensor>,
+    index: Vec<Tensor>,
+}
+
+// (num_nodes, num_heads, head_dim) x (num_heads, head_dim, head_dim)
+fn per_head_matmul(xs: &Tensor, ws: &Tensor) -> Result<Tensor> {
+    xs.transpose(0, 1)?
+        .contiguous()?
+        .matmul(ws)?
+        .transpose(0, 1)?
+        .contiguous()
+}
+
+/// https://arxiv.org/abs/2003.01332
+/// - Heterogeneous Mutual Attention: type-specific key/query projections and
+///   relation-specific attention matrices with a learnable prior per head
+/// - Heterogeneous Message Passing: type-specific value projections and
+///   relation-specific message matrices
+/// - Target-Specific Aggregation: the softmax runs over all incoming edges of a
+///   node across the relations, followed by GELU, a type-specific output projection
+///   and a gated skip connection when the input and output dimensions agree
+///
+/// All node types are mapped to the same `out_dim`, split into `num_heads` heads.
+/// Edge type `(dst, rel, src)` carries messages from `edge_index[1]` of type `src`
+/// into `edge_index[0]` of type `dst`. Relations missing from the input are skipped.
+pub struct HgtConv<NodeType, EdgeType> {
+    num_heads: usize,
+    head_dim: usize,
+    nodes: HashMap<NodeType, HgtNode>,
+    edge_types: Vec<(NodeType, EdgeType, NodeType)>,
+    relations: Vec<HgtRelation>,
+}
+impl<NodeType, EdgeType> HgtConv<NodeType, EdgeType>
+where
+    NodeType: Clone + Eq + Hash + ToString,
+    EdgeType: Clone + Eq + Hash + ToString,
+{
+    pub fn new(
+        in_dims: &[(NodeType, usize)],
+        out_dim: usize,
+        num_heads: usize,
+        edge_types: &[(NodeType, EdgeType, NodeType)],
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        if num_heads == 0 || out_dim % num_heads != 0 {
+            bail!(
+                "out_dim {} must be divisible by num_heads {}",
+                out_dim,
+                num_heads
+            )
+        }
+        let head_dim = out_dim / num_heads;
+
+        let mut nodes = HashMap::new();
+        for (node_type, in_dim) in in_dims.iter().cloned() {
+            let name = node_type.to_string();
+            let skip = if in_dim == out_dim {
+                Some(vs.get_with_hints(1, &format!("skip[{}]", name), Init::Const(1.0))?)
+            } else {
+                None
+            };
+            nodes.insert(
+                node_type,
+                HgtNode {
+                    k_lin: linear(in_dim, out_dim, vs.pp(format!("k_lin[{}]", name)))?,
+                    q_lin: linear(in_dim, out_dim, vs.pp(format!("q_lin[{}]", name)))?,
+                    v_lin: linear(in_dim, out_dim, vs.pp(format!("v_lin[{}]", name)))?,
+                    a_lin: linear(out_dim, out_dim, vs.pp(format!("a_lin[{}]", name)))?,
+                    skip,
+                },
+            );
+        }
+
+        let bound = (6.0 / (2 * head_dim) as f64).sqrt();
+        let init = Init::Uniform {
+            lo: -bound,
+            up: bound,
+        };
+        let mut relations = Vec::new();
+        let edge_types: Vec<_> = edge_types
+            .iter()
+            .filter(|edge_type| {
+                nodes.contains_key(&edge_type.0) && nodes.contains_key(&edge_type.2)
+            })
+            .cloned()
+            .collect();
+        for edge_type in &edge_types {
+            let name = format!(
+                "{},{},{}",
+                edge_type.0.to_string(),
+                edge_type.1.to_string(),
+                edge_type.2.to_string()
+            );
+            relations.push(HgtRelation {
+                att: vs.get_with_hints(
+                    (num_heads, head_dim, head_dim),
+                    &format!("att[{}]", name),
+                    init,
+                )?,
+                msg: vs.get_with_hints(
+                    (num_heads, head_dim, head_dim),
+                    &format!("msg[{}]", name),
+                    init,
+                )?,
+                prior: vs.get_with_hints(
+                    (1, num_heads),
+                    &format!("prior[{}]", name),
+                    Init::Const(1.0),
+                )?,
+            });
+        }
+        Ok(Self {
+            num_heads,
+            head_dim,
+            nodes,
+            edge_types,
+            relations,
+        })
+    }
+}
+impl<NodeType, EdgeType> HeteroGnnModule<NodeType, EdgeType> for HgtConv<NodeType, EdgeType>
+where
+    NodeType: Clone + Eq + Hash + ToString,
+    EdgeType: Clone + Eq + Hash + ToString,
+{
+    fn forward_t(
+        &self,
+        xs: &HashMap<NodeType, Tensor>,
+        edge_index: &HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+        _train: bool,
+    ) -> Result<HashMap<NodeType, Tensor>> {
+        let split = |h: Tensor| h.reshape(((), self.num_heads, self.head_dim));
+        let mut ks = HashMap::new();
+        let mut qs = HashMap::new();
+        let mut values = HashMap::new();
+        for (node_type, node) in &self.nodes {
+            let x = &xs[node_type];
+            ks.insert(node_type.clone(), split(node.k_lin.forward(x)?)?);
+            qs.insert(node_type.clone(), split(node.q_lin.forward(x)?)?);
+            values.insert(node_type.clone(), split(node.v_lin.forward(x)?)?);
+        }
+
+        // concatenated in the order of the relations given at construction
+        let mut incoming: HashMap<NodeType, Incoming> = HashMap::new();
+        for (edge_type, relation) in self.edge_types.iter().zip(&self.relations) {
+            let Some(edge_index) = edge_index.get(edge_type) else {
+                continue;
+            };
+            let (dst, src) = (edge_index.i((0, ..))?, edge_index.i((1, ..))?);
+            let k = per_head_matmul(&ks[&edge_type.2], &relation.att)?.i(&src)?;
+            let q = qs[&edge_type.0].i(&dst)?;
+            let score = (k * q)?
+                .sum(2)?
+                .broadcast_mul(&relation.prior)?
+                .affine(1.0 / (self.head_dim as f64).sqrt(), 0.0)?;
+            let message = per_head_matmul(&values[&edge_type.2], &relation.msg)?.i(&src)?;
+            let entry = incoming.entry(edge_type.0.clone()).or_default();
+            entry.scores.push(score);
+            entry.messages.push(message);
+            entry.index.push(dst);
+        }
+
+        let mut output = HashMap::new();
+        for (node_type, node) in &self.nodes {
+            let x = &xs[node_type];
+            let num_nodes = x.dim(0)?;
+            let aggr = match incoming.get(node_type) {
+                Some(incoming) => {
+                    let index = Tensor::cat(&incoming.index, 0)?;
+                    let scores = Tensor::cat(&incoming.scores, 0)?;
+                    let alpha = segment_softmax(&scores, &index, num_nodes)?;
+                    let messages =
+                        Tensor::cat(&incoming.messages, 0)?.broadcast_mul(&alpha.unsqueeze(2)?)?;
+                    scatter_sum(&messages, &index, num_nodes)?
+                }
+                None => Tensor::zeros(
+                    (num_nodes, self.num_heads, self.head_dim),
+                    x.dtype(),
+                    x.device(),
+                )?,
+            };
+            let out = node.a_lin.forward(&aggr.flatten_from(1)?.gelu_erf()?)?;
+            let out = match &node.skip {
+                Some(skip) => {
+                    let alpha = candle_nn::ops::sigmoid(skip)?;
+                    (out.broadcast_mul(&alpha)? + x.broadcast_mul(&(1.0 - alpha)?)?)?
+                }
+                None => out,
+            };
+            output.insert(node_type.clone(), out);
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device};
+    use candle_nn::VarMap;
+
+    use super::*;
+
+    #[test]
+    fn test_hgt_conv() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let conv = HgtConv::new(
+            &[('a', 3), ('b', 8)],
+            8,
+            2,
+            &[('a', 'x', 'b'), ('b', 'y', 'a'), ('b', 'z', 'b')],
+            vs.pp("hgt"),
+        )?;
+        let xs = HashMap::from([
+            ('a', Tensor::randn(0f32, 1f32, (4, 3), &device)?),
+            ('b', Tensor::randn(0f32, 1f32, (5, 8), &device)?),
+        ]);
+        let mut edge_index = HashMap::from([
+            (
+                ('a', 'x', 'b'),
+                Tensor::new(&[[0u32, 0, 1], [0, 1, 4]], &device)?,
+            ),
+            (('b', 'y', 'a'), Tensor::new(&[[2u32, 3], [0, 3]], &device)?),
+        ]);
+        let output = conv.forward(&xs, &edge_index)?;
+        assert_eq!(output[&'a'].dims(), &[4, 8]);
+        assert_eq!(output[&'b'].dims(), &[5, 8]);
+
+        // a relation into ('b', 0) only changes the output of ('b', 0)
+        edge_index.insert(('b', 'z', 'b'), Tensor::new(&[[0u32], [1]], &device)?);
+        let updated = conv.forward(&xs, &edge_index)?;
+        let diff = (&updated[&'b'] - &output[&'b'])?
+            .abs()?
+            .sum(1)?
+            .to_vec1::<f32>()?;
+        assert!(diff[0] > 0.0);
+        assert_eq!(&diff[1..], &[0.0; 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hgt_conv_values() -> Result<()> {
+        let device = Device::Cpu;
+        let scalar = |w: f32| Tensor::new(&[[w]], &device);
+        // one head of dimension 1: k_b = 2 x, v_b = 3 x and the other projections are x
+        let mut tensors = HashMap::new();
+        for (node_type, k, v) in [('a', 1.0, 1.0), ('b', 2.0, 3.0)] {
+            for (lin, w) in [("k_lin", k), ("q_lin", 1.0), ("v_lin", v), ("a_lin", 1.0)] {
+                let name = format!("{}[{}]", lin, node_type);
+                tensors.insert(format!("{}.weight", name), scalar(w)?);
+                tensors.insert(
+                    format!("{}.bias", name),
+                    Tensor::zeros(1, DType::F32, &device)?,
+                );
+            }
+            // sigmoid(0) = 0.5 of the output and of the input
+            tensors.insert(
+                format!("skip[{}]", node_type),
+                Tensor::zeros(1, DType::F32, &device)?,
+            );
+        }
+        for (relation, msg, prior) in [("a,x,b", 1.0, 1.0), ("a,y,a", 2.0, 0.5)] {
+            tensors.insert(format!("att[{}]", relation), scalar(1.0)?.unsqueeze(0)?);
+            tensors.insert(format!("msg[{}]", relation), scalar(msg)?.unsqueeze(0)?);
+            tensors.insert(format!("prior[{}]", relation), scalar(prior)?);
+        }
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let conv = HgtConv::new(
+            &[('a', 1), ('b', 1)],
+            1,
+            1,
+            &[('a', 'x', 'b'), ('a', 'y', 'a')],
+            vs,
+        )?;
+        let xs = HashMap::from([
+            ('a', Tensor::new(&[[1f32], [-1.]], &device)?),
+            ('b', Tensor::new(&[[2f32]], &device)?),
+        ]);
+        // ('a', 0) receives from ('b', 0) and from ('a', 1)
+        let edge_index = HashMap::from([
+            (('a', 'x', 'b'), Tensor::new(&[[0u32], [0]], &device)?),
+            (('a', 'y', 'a'), Tensor::new(&[[0u32], [1]], &device)?),
+        ]);
+        let output = conv.forward(&xs, &edge_index)?;
+
+        // scores q k prior = 1 * 4 * 1 and 1 * -1 * 0.5, messages 6 * 1 and -1 * 2,
+        // with a single softmax over both relations
+        let (e_x, e_y) = (4f32.exp(), (-0.5f32).exp());
+        let aggr = (6.0 * e_x - 2.0 * e_y) / (e_x + e_y);
+        let expected =
+            ((Tensor::new(&[[aggr], [0.0]], &device)?.gelu_erf()? * 0.5)? + (&xs[&'a'] * 0.5)?)?;
+        let diff = (&output[&'a'] - expected)?
+            .abs()?
+            .max_all()?
+            .to_scalar::<f32>()?;
+        assert!(diff < 1e-5);
+        // no incoming relation: only the skip connection remains
+        assert_eq!(output[&'b'].to_vec2::<f32>()?, [[1.0]]);
+        Ok(())
+    }
+}
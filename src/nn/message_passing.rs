@@ -0,0 +1,172 @@
+use candle_core::{bail, IndexOp, Result, Tensor};
+
+use super::traits::{GnnModule, GnnModuleWithEdges};
+use super::utils::{
+    scatter_max, scatter_mean, scatter_min, scatter_powermean, scatter_softmax, scatter_std,
+    scatter_sum, scatter_var,
+};
+use crate::EdgeIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Aggregation {
+    #[default]
+    Sum,
+    Mean,
+    Max,
+    Min,
+    Var,
+    Std,
+    /// Softmax aggregation with inverse temperature `t`.
+    Softmax(f64),
+    /// Power mean aggregation with exponent `p`.
+    PowerMean(f64),
+}
+impl Aggregation {
+    pub fn aggregate(&self, messages: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+        match *self {
+            Self::Sum => scatter_sum(messages, index, dim_size),
+            Self::Mean => scatter_mean(messages, index, dim_size),
+            Self::Max => scatter_max(messages, index, dim_size),
+            Self::Min => scatter_min(messages, index, dim_size),
+            Self::Var => scatter_var(messages, index, dim_size),
+            Self::Std => scatter_std(messages, index, dim_size),
+            Self::Softmax(t) => scatter_softmax(messages, index, dim_size, t),
+            Self::PowerMean(p) => scatter_powermean(messages, index, dim_size, p),
+        }
+    }
+}
+
+/// Message passing scheme
+///
+///   h = transform(x)
+///   m_ij = message(h_i, h_j)          for each edge (i, j) in edge_index
+///   a_i = aggregate({m_ij : j})
+///   out_i = update(a_i, h_i)
+///
+/// Messages flow from `edge_index[1]` (j) into `edge_index[0]` (i).
+/// Every `MessagePassing` is a `GnnModule` and a `GnnModuleWithEdges`, so a custom
+/// layer only needs `message`.
+pub trait MessagePassing {
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::Sum
+    }
+    /// Rewrites the edges before propagation, e.g., to add self-loops or normalize weights.
+    fn prepare_edges(
+        &self,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+    ) -> Result<(EdgeIndex, Option<Tensor>, Option<Tensor>)> {
+        Ok((edge_index.clone(), edge_weight.cloned(), edge_attr.cloned()))
+    }
+    fn transform(&self, xs: &Tensor, _train: bool) -> Result<Tensor> {
+        Ok(xs.clone())
+    }
+    /// `x_i` and `x_j` are the transformed features gathered at `edge_index[0]` and
+    /// `edge_index[1]`, respectively. `edge_weight` and `edge_attr` are given as is.
+    fn message(
+        &self,
+        x_i: &Tensor,
+        x_j: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor>;
+    fn aggregate(&self, messages: &Tensor, index: &Tensor, num_nodes: usize) -> Result<Tensor> {
+        self.aggregation().aggregate(messages, index, num_nodes)
+    }
+    fn update(&self, aggr: &Tensor, _xs: &Tensor, _train: bool) -> Result<Tensor> {
+        Ok(aggr.clone())
+    }
+    fn propagate(
+        &self,
+        xs: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        let num_nodes = edge_index.num_nodes();
+        if xs.dim(0)? != num_nodes {
+            bail!("{} node features for {} nodes", xs.dim(0)?, num_nodes)
+        }
+        for edge_input in [edge_weight, edge_attr].into_iter().flatten() {
+            if edge_input.dim(0)? != edge_index.num_edges() {
+                bail!(
+                    "{} edge inputs for {} edges",
+                    edge_input.dim(0)?,
+                    edge_index.num_edges()
+                )
+            }
+        }
+        let (edge_index, edge_weight, edge_attr) =
+            self.prepare_edges(edge_index, edge_weight, edge_attr)?;
+        let h = self.transform(xs, train)?;
+        let index = edge_index.row(0)?;
+        let x_i = h.i(&index)?;
+        let x_j = h.i(&edge_index.row(1)?)?;
+        let messages = self.message(
+            &x_i,
+            &x_j,
+            &edge_index,
+            edge_weight.as_ref(),
+            edge_attr.as_ref(),
+            train,
+        )?;
+        let aggr = self.aggregate(&messages, &index, num_nodes)?;
+        self.update(&aggr, &h, train)
+    }
+}
+impl<T: MessagePassing> GnnModule for T {
+    fn forward_t(&self, xs: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        self.propagate(xs, edge_index, None, None, train)
+    }
+}
+impl<T: MessagePassing> GnnModuleWithEdges for T {
+    fn forward_with_edges_t(
+        &self,
+        xs: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor> {
+        self.propagate(xs, edge_index, edge_weight, edge_attr, train)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::Device;
+
+    use super::*;
+
+    struct MaxDiff;
+    impl MessagePassing for MaxDiff {
+        fn aggregation(&self) -> Aggregation {
+            Aggregation::Max
+        }
+        fn message(
+            &self,
+            x_i: &Tensor,
+            x_j: &Tensor,
+            _edge_index: &EdgeIndex,
+            _edge_weight: Option<&Tensor>,
+            _edge_attr: Option<&Tensor>,
+            _train: bool,
+        ) -> Result<Tensor> {
+            x_j - x_i
+        }
+    }
+
+    #[test]
+    fn test_custom_message() -> Result<()> {
+        let device = Device::Cpu;
+        let xs = Tensor::new(&[[1f32], [3.], [4.]], &device)?;
+        let edge_index = EdgeIndex::new(Tensor::new(&[[0u32, 0, 1], [1, 2, 0]], &device)?, 3)?;
+        let out = MaxDiff.forward(&xs, &edge_index)?;
+        assert_eq!(out.to_vec2::<f32>()?, [[3.], [-2.], [0.]]);
+        Ok(())
+    }
+}
@@ -2,12 +2,23 @@ mod traits;
 pub use traits::*;
 pub mod utils;
 
+mod message_passing;
+pub use message_passing::{Aggregation, MessagePassing};
+
 mod gcn;
-pub use gcn::{Gcn, GcnConv, GcnParams};
+pub use gcn::{Gcn, GcnConv, GcnNormalization, GcnParams, NormalizedAdjacency};
 mod gin;
-pub use gin::{Gin, GinConv};
+pub use gin::{Gin, GinConv, GinParams, GineConv};
 mod gat;
-pub use gat::{Gat, GatConv};
+pub use gat::{Gat, GatConv, GatParams, GatV2Conv, GatV2Params};
+mod sage;
+pub use sage::{Sage, SageAggregator, SageConv, SageParams};
 
 mod hetero_gcn;
-pub use hetero_gcn::{hetero_gcn, HeteroGcnConv};
+pub use hetero_gcn::{hetero_gcn, HeteroGcn, HeteroGcnAggregation, HeteroGcnConv, HeteroGcnParams};
+mod hgt;
+pub use hgt::HgtConv;
+mod to_hetero;
+pub use to_hetero::{to_hetero, HeteroAggregation, ToHetero};
+mod rgcn;
+pub use rgcn::{RgcnConv, RgcnDecomposition};
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use candle_core::{bail, DType, IndexOp, Result, Shape, Tensor};
+use candle_nn::{Init, VarBuilder};
+
+use super::utils::scatter_sum;
+use crate::EdgeIndex;
+
+/// Parametrization of the relation weights W_r of `RgcnConv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RgcnDecomposition {
+    /// A full `(in_dim, out_dim)` weight per relation.
+    #[default]
+    None,
+    /// W_r = sum_b a_rb V_b with `num_bases` bases V_b shared by all relations.
+    Basis(usize),
+    /// W_r = diag(Q_r1, ..., Q_rK) with `num_blocks` blocks; `in_dim` and `out_dim`
+    /// must be divisible by `num_blocks`.
+    BlockDiagonal(usize),
+}
+
+enum RelationWeights {
+    /// (num_relations, in_dim, out_dim)
+    Full(Tensor),
+    /// bases: (num_bases, in_dim, out_dim), comp: (num_relations, num_bases)
+    Basis { bases: Tensor, comp: Tensor },
+    /// (num_relations, num_blocks, in_dim / num_blocks, out_dim / num_blocks)
+    BlockDiagonal(Tensor),
+}
+
+fn glorot<S: Into<Shape>>(shape: S, fan: usize, name: &str, vs: &VarBuilder) -> Result<Tensor> {
+    let bound = (6.0 / fan as f64).sqrt();
+    vs.get_with_hints(
+        shape,
+        name,
+        Init::Uniform {
+            lo: -bound,
+            up: bound,
+        },
+    )
+}
+
+/// https://arxiv.org/pdf/1703.06103.pdf
+/// - out_i = W_0 x_i + sum_r sum_{j in N_r(i)} 1 / |N_r(i)| W_r x_j + b
+/// - All nodes share a single node set. The relation of each edge is given by an
+///   `edge_type` tensor of shape `(num_edges,)` with values in `0..num_relations`.
+/// - Messages flow from `edge_index[1]` into `edge_index[0]`.
+pub struct RgcnConv {
+    in_dim: usize,
+    out_dim: usize,
+    num_relations: usize,
+    weights: RelationWeights,
+    root: Tensor,
+    bias: Tensor,
+}
+impl RgcnConv {
+    pub fn new(
+        in_dim: usize,
+        out_dim: usize,
+        num_relations: usize,
+        decomposition: RgcnDecomposition,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let weights = match decomposition {
+            RgcnDecomposition::None => RelationWeights::Full(glorot(
+                (num_relations, in_dim, out_dim),
+                in_dim + out_dim,
+                "weight",
+                &vs,
+            )?),
+            RgcnDecomposition::Basis(num_bases) => RelationWeights::Basis {
+                bases: glorot((num_bases, in_dim, out_dim), in_dim + out_dim, "bases", &vs)?,
+                comp: glorot(
+                    (num_relations, num_bases),
+                    num_relations + num_bases,
+                    "comp",
+                    &vs,
+                )?,
+            },
+            RgcnDecomposition::BlockDiagonal(num_blocks) => {
+                if num_blocks == 0 || in_dim % num_blocks != 0 || out_dim % num_blocks != 0 {
+                    bail!(
+                        "in_dim {} and out_dim {} must be divisible by num_blocks {}",
+                        in_dim,
+                        out_dim,
+                        num_blocks
+                    )
+                }
+                let (block_in, block_out) = (in_dim / num_blocks, out_dim / num_blocks);
+                RelationWeights::BlockDiagonal(glorot(
+                    (num_relations, num_blocks, block_in, block_out),
+                    block_in + block_out,
+                    "blocks",
+                    &vs,
+                )?)
+            }
+        };
+        Ok(Self {
+            in_dim,
+            out_dim,
+            num_relations,
+            weights,
+            root: glorot((in_dim, out_dim), in_dim + out_dim, "root", &vs)?,
+            bias: vs.get_with_hints((1, out_dim), "bias", Init::Const(0.0))?,
+        })
+    }
+
+    /// Materializes W_r as a `(num_relations, in_dim, out_dim)` tensor.
+    pub fn relation_weights(&self) -> Result<Tensor> {
+        match &self.weights {
+            RelationWeights::Full(weight) => Ok(weight.clone()),
+            RelationWeights::Basis { bases, comp } => comp
+                .matmul(&bases.flatten_from(1)?)?
+                .reshape((self.num_relations, self.in_dim, self.out_dim)),
+            RelationWeights::BlockDiagonal(blocks) => {
+                let (r, k, block_in, block_out) = blocks.dims4()?;
+                let rows = (0..k)
+                    .map(|i| {
+                        let left = Tensor::zeros(
+                            (r, block_in, i * block_out),
+                            blocks.dtype(),
+                            blocks.device(),
+                        )?;
+                        let right = Tensor::zeros(
+                            (r, block_in, (k - i - 1) * block_out),
+                            blocks.dtype(),
+                            blocks.device(),
+                        )?;
+                        Tensor::cat(&[&left, &blocks.i((.., i))?, &right], 2)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Tensor::cat(&rows, 1)
+            }
+        }
+    }
+
+    // W_{type(e)} x_{j(e)} for every edge e
+    fn messages(&self, xs: &Tensor, src: &Tensor, edge_type: &Tensor) -> Result<Tensor> {
+        let num_edges = src.dim(0)?;
+        match &self.weights {
+            RelationWeights::Full(weight) => {
+                // (num_relations, num_nodes, out_dim), indexed by type * num_nodes + j
+                let num_nodes = xs.dim(0)?;
+                let h = xs.broadcast_matmul(weight)?.flatten_to(1)?;
+                let index = ((edge_type.to_dtype(DType::I64)? * num_nodes as f64)?
+                    + src.to_dtype(DType::I64)?)?;
+                h.i(&index)
+            }
+            RelationWeights::Basis { bases, comp } => {
+                let num_bases = comp.dim(1)?;
+                // (num_nodes, num_bases, out_dim)
+                let h = xs
+                    .matmul(&bases.permute((1, 0, 2))?.flatten_from(1)?)?
+                    .reshape(((), num_bases, self.out_dim))?;
+                h.i(src)?
+                    .broadcast_mul(&comp.i(edge_type)?.unsqueeze(2)?)?
+                    .sum(1)
+            }
+            RelationWeights::BlockDiagonal(blocks) => {
+                let (_, k, block_in, _) = blocks.dims4()?;
+                xs.i(src)?
+                    .reshape((num_edges, k, 1, block_in))?
+                    .matmul(&blocks.i(edge_type)?.contiguous()?)?
+                    .reshape((num_edges, self.out_dim))
+            }
+        }
+    }
+
+    pub fn forward(
+        &self,
+        xs: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_type: &Tensor,
+    ) -> Result<Tensor> {
+        let num_nodes = edge_index.num_nodes();
+        if xs.dim(0)? != num_nodes {
+            bail!("{} node features for {} nodes", xs.dim(0)?, num_nodes)
+        }
+        if edge_type.dims() != [edge_index.num_edges()] || edge_type.dtype() != DType::U32 {
+            bail!(
+                "edge_type must be u32 of shape ({},), got {:?} {:?}",
+                edge_index.num_edges(),
+                edge_type.dtype(),
+                edge_type.shape()
+            )
+        }
+        let dst = edge_index.row(0)?;
+        let types = edge_type.to_vec1::<u32>()?;
+        if let Some(&t) = types.iter().find(|&&t| t as usize >= self.num_relations) {
+            bail!(
+                "edge_type contains {} but num_relations is {}",
+                t,
+                self.num_relations
+            )
+        }
+
+        // 1 / |N_r(i)| for every edge
+        let dsts = dst.to_vec1::<u32>()?;
+        let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+        for key in dsts.iter().cloned().zip(types.iter().cloned()) {
+            *counts.entry(key).or_default() += 1;
+        }
+        let norm: Vec<f32> = dsts
+            .iter()
+            .cloned()
+            .zip(types.iter().cloned())
+            .map(|key| 1.0 / counts[&key] as f32)
+            .collect();
+        let norm = Tensor::from_vec(norm, (types.len(), 1), xs.device())?.to_dtype(xs.dtype())?;
+
+        let messages = self
+            .messages(xs, &edge_index.row(1)?, edge_type)?
+            .broadcast_mul(&norm)?;
+        let aggr = scatter_sum(&messages, &dst, num_nodes)?;
+        (aggr + xs.matmul(&self.root)?)?.broadcast_add(&self.bias)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::Device;
+    use candle_nn::VarMap;
+
+    use super::*;
+
+    #[test]
+    fn test_rgcn_conv() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 4), &device)?;
+        let edges = [
+            (0, 1, 0),
+            (0, 2, 0),
+            (0, 3, 1),
+            (1, 0, 2),
+            (2, 0, 1),
+            (3, 0, 1),
+        ];
+        let mut index: Vec<u32> = edges.iter().map(|e| e.0).collect();
+        index.extend(edges.iter().map(|e| e.1));
+        let edge_index = EdgeIndex::new(Tensor::from_vec(index, (2, edges.len()), &device)?, 4)?;
+        let types: Vec<u32> = edges.iter().map(|e| e.2).collect();
+        let edge_type = Tensor::new(types.as_slice(), &device)?;
+
+        for (name, decomposition) in [
+            ("none", RgcnDecomposition::None),
+            ("basis", RgcnDecomposition::Basis(2)),
+            ("block", RgcnDecomposition::BlockDiagonal(2)),
+        ] {
+            let conv = RgcnConv::new(4, 6, 3, decomposition, vs.pp(name))?;
+            let ys = conv
+                .forward(&xs, &edge_index, &edge_type)?
+                .to_vec2::<f32>()?;
+
+            // out_i = x_i W_0 + b + sum_r mean_{j in N_r(i)} x_j W_r
+            let x = xs.to_vec2::<f32>()?;
+            let w = conv.relation_weights()?.to_vec3::<f32>()?;
+            let mut expected = xs
+                .matmul(&conv.root)?
+                .broadcast_add(&conv.bias)?
+                .to_vec2::<f32>()?;
+            for &(i, j, r) in &edges {
+                let count = edges.iter().filter(|e| e.0 == i && e.2 == r).count() as f32;
+                for (c, out) in expected[i as usize].iter_mut().enumerate() {
+                    *out += (0..4)
+                        .map(|k| x[j as usize][k] * w[r as usize][k][c])
+                        .sum::<f32>()
+                        / count;
+                }
+            }
+            for (y, e) in ys.iter().flatten().zip(expected.iter().flatten()) {
+                assert!((y - e).abs() < 1e-5, "{}: {} != {}", name, y, e);
+            }
+        }
+
+        // off-diagonal blocks are zero
+        let conv = RgcnConv::new(4, 6, 3, RgcnDecomposition::BlockDiagonal(2), vs.pp("block"))?;
+        let w = conv.relation_weights()?;
+        assert_eq!(
+            w.i((.., 0..2, 3..6))?
+                .abs()?
+                .sum_all()?
+                .to_scalar::<f32>()?,
+            0.0
+        );
+        assert_eq!(
+            w.i((.., 2..4, 0..3))?
+                .abs()?
+                .sum_all()?
+                .to_scalar::<f32>()?,
+            0.0
+        );
+
+        assert!(RgcnConv::new(4, 6, 3, RgcnDecomposition::BlockDiagonal(4), vs.pp("bad")).is_err());
+        let edge_type = Tensor::new(&[0u32, 0, 1, 3, 1, 1], &device)?;
+        assert!(conv.forward(&xs, &edge_index, &edge_type).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use candle_core::{Result, Tensor, D};
+use candle_nn::{Activation, Dropout, Linear, Module, VarBuilder};
+
+use super::message_passing::{Aggregation, MessagePassing};
+use super::traits::GnnModule;
+use super::utils::{linear, linear_no_bias};
+use crate::EdgeIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SageAggregator {
+    #[default]
+    Mean,
+    Max,
+    Sum,
+    /// Max pooling over neighbours transformed by a fully connected layer.
+    Pool,
+}
+
+/// https://arxiv.org/abs/1706.02216
+/// - out = W_l agg(x_j) + W_r x_i
+pub struct SageConv {
+    in_dim: usize,
+    aggregator: SageAggregator,
+    normalize: bool,
+    pool: Option<Linear>,
+    lin_l: Linear,
+    lin_r: Option<Linear>,
+}
+impl SageConv {
+    pub fn new(
+        in_dim: usize,
+        out_dim: usize,
+        aggregator: SageAggregator,
+        root_weight: bool,
+        normalize: bool,
+        vs: VarBuilder,
+    ) -> Result<Self> {
+        let pool = match aggregator {
+            SageAggregator::Pool => Some(linear(in_dim, in_dim, vs.pp("pool"))?),
+            _ => None,
+        };
+        let lin_r = if root_weight {
+            Some(linear_no_bias(in_dim, out_dim, vs.pp("lin_r"))?)
+        } else {
+            None
+        };
+        Ok(Self {
+            in_dim,
+            aggregator,
+            normalize,
+            pool,
+            lin_l: linear(in_dim, out_dim, vs.pp("lin_l"))?,
+            lin_r,
+        })
+    }
+}
+impl MessagePassing for SageConv {
+    fn aggregation(&self) -> Aggregation {
+        match self.aggregator {
+            SageAggregator::Mean => Aggregation::Mean,
+            SageAggregator::Max | SageAggregator::Pool => Aggregation::Max,
+            SageAggregator::Sum => Aggregation::Sum,
+        }
+    }
+    fn transform(&self, xs: &Tensor, _train: bool) -> Result<Tensor> {
+        assert_eq!(xs.dim(1)?, self.in_dim);
+        Ok(xs.clone())
+    }
+    fn message(
+        &self,
+        _x_i: &Tensor,
+        x_j: &Tensor,
+        _edge_index: &EdgeIndex,
+        _edge_weight: Option<&Tensor>,
+        _edge_attr: Option<&Tensor>,
+        _train: bool,
+    ) -> Result<Tensor> {
+        match &self.pool {
+            Some(pool) => pool.forward(x_j)?.relu(),
+            None => Ok(x_j.clone()),
+        }
+    }
+    fn update(&self, aggr: &Tensor, xs: &Tensor, _train: bool) -> Result<Tensor> {
+        let mut out = self.lin_l.forward(aggr)?;
+        if let Some(lin_r) = &self.lin_r {
+            out = (out + lin_r.forward(xs)?)?;
+        }
+        if self.normalize {
+            let norm = out.sqr()?.sum_keepdim(D::Minus1)?.maximum(1e-24)?.sqrt()?;
+            out = out.broadcast_div(&norm)?;
+        }
+        Ok(out)
+    }
+}
+
+pub struct SageParams {
+    pub dropout_rate: f32,
+    pub activation_fn: Activation,
+    pub aggregator: SageAggregator,
+    pub root_weight: bool,
+    pub normalize: bool,
+}
+impl Default for SageParams {
+    fn default() -> Self {
+        Self {
+            dropout_rate: 0.0,
+            activation_fn: Activation::Relu,
+            aggregator: SageAggregator::Mean,
+            root_weight: true,
+            normalize: false,
+        }
+    }
+}
+
+pub struct Sage {
+    layers: Vec<SageConv>,
+    dropout: Dropout,
+    activation_fn: Activation,
+}
+impl Sage {
+    pub fn new(sizes: &[usize], vs: VarBuilder) -> Result<Self> {
+        Self::with_params(sizes, SageParams::default(), vs)
+    }
+    pub fn with_params(sizes: &[usize], params: SageParams, vs: VarBuilder) -> Result<Self> {
+        let mut layers = Vec::new();
+        for i in 0..sizes.len() - 1 {
+            let name = format!("layer_{}", i);
+            layers.push(SageConv::new(
+                sizes[i],
+                sizes[i + 1],
+                params.aggregator,
+                params.root_weight,
+                params.normalize,
+                vs.pp(name),
+            )?);
+        }
+        Ok(Self {
+            layers,
+            dropout: Dropout::new(params.dropout_rate),
+            activation_fn: params.activation_fn,
+        })
+    }
+}
+impl GnnModule for Sage {
+    fn forward_t(&self, x: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor> {
+        let mut h = self.layers[0].forward(x, edge_index)?;
+        for layer in &self.layers[1..] {
+            h = self.dropout.forward(&h, train)?;
+            h = self.activation_fn.forward(&h)?;
+            h = layer.forward(&h, edge_index)?;
+        }
+        Ok(h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{DType, Device};
+    use candle_nn::VarMap;
+
+    use super::*;
+
+    #[test]
+    fn test_sage() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let xs = Tensor::randn(0f32, 1f32, (4, 3), &device)?;
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?, 4)?;
+        for aggregator in [
+            SageAggregator::Mean,
+            SageAggregator::Max,
+            SageAggregator::Sum,
+            SageAggregator::Pool,
+        ] {
+            let params = SageParams {
+                aggregator,
+                normalize: true,
+                ..Default::default()
+            };
+            let model = Sage::with_params(&[3, 8, 2], params, vs.pp(format!("{:?}", aggregator)))?;
+            let ys = model.forward(&xs, &edge_index)?;
+            assert_eq!(ys.dims(), &[4, 2]);
+            let norm = ys.sqr()?.sum(1)?.to_vec1::<f32>()?;
+            for n in norm {
+                assert!((n - 1.0).abs() < 1e-4);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sage_conv() -> Result<()> {
+        let device = Device::Cpu;
+        let tensors = HashMap::from([
+            (
+                "lin_l.weight".to_string(),
+                Tensor::new(&[[1f32, 0.], [0., 1.]], &device)?,
+            ),
+            (
+                "lin_l.bias".to_string(),
+                Tensor::new(&[0.5f32, -0.5], &device)?,
+            ),
+            (
+                "lin_r.weight".to_string(),
+                Tensor::new(&[[1f32, 1.], [0., -1.]], &device)?,
+            ),
+        ]);
+        let vs = VarBuilder::from_tensors(tensors, DType::F32, &device);
+        let xs = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 6.]], &device)?;
+        // node 0 receives from 1 and 2, nodes 1 and 2 from 0
+        let edge_index =
+            EdgeIndex::new(Tensor::new(&[[0u32, 0, 1, 2], [1, 2, 0, 0]], &device)?, 3)?;
+        for (aggregator, expected) in [
+            (
+                SageAggregator::Mean,
+                [[7.5f32, 2.5], [8.5, -2.5], [12.5, -4.5]],
+            ),
+            (
+                SageAggregator::Sum,
+                [[11.5, 7.5], [8.5, -2.5], [12.5, -4.5]],
+            ),
+            (SageAggregator::Max, [[8.5, 3.5], [8.5, -2.5], [12.5, -4.5]]),
+        ] {
+            let conv = SageConv::new(2, 2, aggregator, true, false, vs.clone())?;
+            let ys = conv.forward(&xs, &edge_index)?;
+            assert_eq!(ys.to_vec2::<f32>()?, expected, "{:?}", aggregator);
+        }
+        Ok(())
+    }
+}
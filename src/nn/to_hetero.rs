@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use candle_core::{bail, Result, Tensor};
+use candle_nn::VarBuilder;
+
+use super::{GnnModule, HeteroGnnModule};
+use crate::EdgeIndex;
+
+/// Reduction of the outputs of the relations sharing a destination node type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeteroAggregation {
+    #[default]
+    Sum,
+    Mean,
+    Max,
+}
+impl HeteroAggregation {
+    /// Reduces `outputs`, each of shape `(num_nodes, dim)`, in the given order.
+    pub fn reduce(&self, outputs: &[Tensor]) -> Result<Tensor> {
+        let stacked = Tensor::stack(outputs, 0)?;
+        match self {
+            Self::Sum => stacked.sum(0),
+            Self::Mean => stacked.mean(0),
+            Self::Max => stacked.max(0),
+        }
+    }
+}
+
+/// Heterogeneous module made of one homogeneous `GnnModule` per relation.
+///
+/// Edge type `(dst, rel, src)` carries messages from `edge_index[1]` of type `src`
+/// into `edge_index[0]` of type `dst`. For `src != dst`, the module runs on the union
+/// of the two node sets, the `dst` nodes first, and only the `dst` rows are kept.
+/// The features of the narrower type are zero-padded, so the module of a bipartite
+/// relation takes `max(dim_src, dim_dst)` input features.
+///
+/// There are no separate projections per node type: the features of `src` and `dst`
+/// share the input space of the module, and any weight the module applies to all the
+/// nodes, e.g., the linear layer of `GcnConv`, is shared by both types. The padded
+/// columns are zero, so the weights on them only ever see the wider type.
+/// Modules with distinct root and neighbor weights, e.g., `SageConv`, keep the two
+/// types apart.
+///
+/// The outputs of the relations into a node type are reduced in the order of the
+/// edge types given at construction. Node types without incoming relations in the
+/// input are absent from the output.
+pub struct ToHetero<NodeType, EdgeType, M> {
+    edge_types: Vec<(NodeType, EdgeType, NodeType)>,
+    modules: Vec<M>,
+    aggregation: HeteroAggregation,
+}
+
+/// Builds a `ToHetero` calling `factory` once per edge type in `metadata`.
+pub fn to_hetero<NodeType, EdgeType, M, F>(
+    metadata: (&[NodeType], &[(NodeType, EdgeType, NodeType)]),
+    factory: F,
+    aggregation: HeteroAggregation,
+    vs: VarBuilder,
+) -> Result<ToHetero<NodeType, EdgeType, M>>
+where
+    NodeType: Clone + Eq + Hash + ToString,
+    EdgeType: Clone + Eq + Hash + ToString,
+    M: GnnModule,
+    F: Fn(&(NodeType, EdgeType, NodeType), VarBuilder) -> Result<M>,
+{
+    let (node_types, edge_types) = metadata;
+    let mut modules = Vec::new();
+    for edge_type in edge_types {
+        if !node_types.contains(&edge_type.0) || !node_types.contains(&edge_type.2) {
+            bail!(
+                "edge type ({}, {}, {}) refers to an unknown node type",
+                edge_type.0.to_string(),
+                edge_type.1.to_string(),
+                edge_type.2.to_string()
+            )
+        }
+        let name = format!(
+            "{},{},{}",
+            edge_type.0.to_string(),
+            edge_type.1.to_string(),
+            edge_type.2.to_string()
+        );
+        modules.push(factory(edge_type, vs.pp(name))?);
+    }
+    Ok(ToHetero {
+        edge_types: edge_types.to_vec(),
+        modules,
+        aggregation,
+    })
+}
+
+impl<NodeType, EdgeType, M> HeteroGnnModule<NodeType, EdgeType> for ToHetero<NodeType, EdgeType, M>
+where
+    NodeType: Clone + Eq + Hash,
+    EdgeType: Clone + Eq + Hash,
+    M: GnnModule,
+{
+    fn forward_t(
+        &self,
+        xs: &HashMap<NodeType, Tensor>,
+        edge_index: &HashMap<(NodeType, EdgeType, NodeType), Tensor>,
+        train: bool,
+    ) -> Result<HashMap<NodeType, Tensor>> {
+        let mut order = Vec::new();
+        let mut grouped: HashMap<NodeType, Vec<Tensor>> = HashMap::new();
+        for (edge_type, module) in self.edge_types.iter().zip(&self.modules) {
+            let Some(index) = edge_index.get(edge_type) else {
+                continue;
+            };
+            let (dst, src) = (&edge_type.0, &edge_type.2);
+            let output = if dst == src {
+                let x = &xs[dst];
+                let index = EdgeIndex::new(index.clone(), x.dim(0)?)?;
+                module.forward_t(x, &index, train)?
+            } else {
+                let (x_dst, x_src) = (&xs[dst], &xs[src]);
+                let (num_dst, dim_dst) = x_dst.dims2()?;
+                let (num_src, dim_src) = x_src.dims2()?;
+                let dim = dim_dst.max(dim_src);
+                let x = Tensor::cat(
+                    &[
+                        x_dst.pad_with_zeros(1, 0, dim - dim_dst)?,
+                        x_src.pad_with_zeros(1, 0, dim - dim_src)?,
+                    ],
+                    0,
+                )?;
+                let rows = index.to_vec2::<u32>()?;
+                if rows.len() != 2 {
+                    bail!(
+                        "edge index must be of shape (2, num_edges), got {:?}",
+                        index.shape()
+                    )
+                }
+                for (row, num_nodes) in [(&rows[0], num_dst), (&rows[1], num_src)] {
+                    if let Some(&i) = row.iter().find(|&&i| i as usize >= num_nodes) {
+                        bail!("edge index contains {} but num_nodes is {}", i, num_nodes)
+                    }
+                }
+                let offset = Tensor::new(&[[0u32], [num_dst as u32]], index.device())?;
+                // the edges go from the `src` block to the `dst` block, none is reversed
+                let index = EdgeIndex::new_unchecked(
+                    index.broadcast_add(&offset)?,
+                    num_dst + num_src,
+                    rows[0].windows(2).all(|w| w[0] <= w[1]),
+                    !rows[0].is_empty(),
+                );
+                module.forward_t(&x, &index, train)?.narrow(0, 0, num_dst)?
+            };
+            if !grouped.contains_key(dst) {
+                order.push(dst.clone());
+            }
+            grouped.entry(dst.clone()).or_default().push(output);
+        }
+        order
+            .into_iter()
+            .map(|node_type| {
+                let output = self.aggregation.reduce(&grouped[&node_type])?;
+                Ok((node_type, output))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device};
+    use candle_nn::VarMap;
+
+    use super::*;
+    use crate::nn::{SageAggregator, SageConv};
+
+    #[test]
+    fn test_to_hetero() -> Result<()> {
+        let device = Device::Cpu;
+        let varmap = VarMap::new();
+        let vs = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+        let dims = HashMap::from([('a', 3), ('b', 2)]);
+        let edge_types = [('a', 'x', 'b'), ('a', 'y', 'a'), ('b', 'z', 'a')];
+        let xs = HashMap::from([
+            ('a', Tensor::randn(0f32, 1f32, (4, 3), &device)?),
+            ('b', Tensor::randn(0f32, 1f32, (5, 2), &device)?),
+        ]);
+        let edge_index = HashMap::from([
+            (
+                ('a', 'x', 'b'),
+                Tensor::new(&[[0u32, 0, 1], [0, 1, 4]], &device)?,
+            ),
+            (('a', 'y', 'a'), Tensor::new(&[[2u32, 3], [0, 0]], &device)?),
+            (('b', 'z', 'a'), Tensor::new(&[[1u32, 2], [3, 3]], &device)?),
+        ]);
+
+        for (name, aggregation) in [
+            ("sum", HeteroAggregation::Sum),
+            ("mean", HeteroAggregation::Mean),
+            ("max", HeteroAggregation::Max),
+        ] {
+            let model = to_hetero(
+                (&['a', 'b'], &edge_types),
+                |edge_type, vs| {
+                    let in_dim = dims[&edge_type.0].max(dims[&edge_type.2]);
+                    SageConv::new(in_dim, 6, SageAggregator::Mean, true, false, vs)
+                },
+                aggregation,
+                vs.pp(name),
+            )?;
+            let output = model.forward(&xs, &edge_index)?;
+            assert_eq!(output[&'a'].dims(), &[4, 6]);
+            assert_eq!(output[&'b'].dims(), &[5, 6]);
+
+            // ('a', 'x', 'b') on the union of the node sets, then ('a', 'y', 'a')
+            let x = Tensor::cat(&[&xs[&'a'], &xs[&'b'].pad_with_zeros(1, 0, 1)?], 0)?;
+            let index = Tensor::new(&[[0u32, 0, 1], [4, 5, 8]], &device)?;
+            let x_out = model.modules[0]
+                .forward(&x, &EdgeIndex::new(index, 9)?)?
+                .narrow(0, 0, 4)?;
+            let y_out = model.modules[1].forward(
+                &xs[&'a'],
+                &EdgeIndex::new(edge_index[&('a', 'y', 'a')].clone(), 4)?,
+            )?;
+            let expected = match aggregation {
+                HeteroAggregation::Sum => (x_out + y_out)?,
+                HeteroAggregation::Mean => ((x_out + y_out)? / 2.0)?,
+                HeteroAggregation::Max => x_out.maximum(&y_out)?,
+            };
+            let diff = (&output[&'a'] - expected)?
+                .abs()?
+                .max_all()?
+                .to_scalar::<f32>()?;
+            assert!(diff < 1e-6, "{}: {}", name, diff);
+        }
+
+        // out of range for 'b' in index[1], then for 'a' in index[0]
+        let model = to_hetero(
+            (&['a', 'b'], &edge_types),
+            |edge_type, vs| {
+                let in_dim = dims[&edge_type.0].max(dims[&edge_type.2]);
+                SageConv::new(in_dim, 6, SageAggregator::Mean, true, false, vs)
+            },
+            HeteroAggregation::Sum,
+            vs.pp("range"),
+        )?;
+        let mut bad_index = edge_index.clone();
+        bad_index.insert(('a', 'x', 'b'), Tensor::new(&[[0u32], [5]], &device)?);
+        assert!(model.forward(&xs, &bad_index).is_err());
+        bad_index.insert(('a', 'x', 'b'), Tensor::new(&[[4u32], [0]], &device)?);
+        assert!(model.forward(&xs, &bad_index).is_err());
+
+        assert!(to_hetero(
+            (&['a'], &edge_types),
+            |_, vs| SageConv::new(3, 6, SageAggregator::Mean, true, false, vs),
+            HeteroAggregation::Sum,
+            vs.pp("bad"),
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -2,12 +2,37 @@ use std::collections::HashMap;
 
 use candle_core::{Result, Tensor};
 
+use crate::{EdgeIndex, HeteroData};
+
 pub trait GnnModule {
     #[allow(unused_variables)]
-    fn forward(&self, xs: &Tensor, edge_index: &Tensor) -> Result<Tensor> {
+    fn forward(&self, xs: &Tensor, edge_index: &EdgeIndex) -> Result<Tensor> {
         self.forward_t(xs, edge_index, false)
     }
-    fn forward_t(&self, xs: &Tensor, edge_index: &Tensor, train: bool) -> Result<Tensor>;
+    fn forward_t(&self, xs: &Tensor, edge_index: &EdgeIndex, train: bool) -> Result<Tensor>;
+}
+
+/// `GnnModule` taking per-edge inputs aligned with the edges of `edge_index`:
+/// - `edge_weight`: `(num_edges,)` scalar weights
+/// - `edge_attr`: `(num_edges, edge_dim)` features
+pub trait GnnModuleWithEdges {
+    fn forward_with_edges(
+        &self,
+        xs: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        self.forward_with_edges_t(xs, edge_index, edge_weight, edge_attr, false)
+    }
+    fn forward_with_edges_t(
+        &self,
+        xs: &Tensor,
+        edge_index: &EdgeIndex,
+        edge_weight: Option<&Tensor>,
+        edge_attr: Option<&Tensor>,
+        train: bool,
+    ) -> Result<Tensor>;
 }
 
 pub trait HeteroGnnModule<NodeType, EdgeType> {
@@ -24,4 +49,17 @@ pub trait HeteroGnnModule<NodeType, EdgeType> {
         edge_index: &HashMap<(NodeType, EdgeType, NodeType), Tensor>,
         train: bool,
     ) -> Result<HashMap<NodeType, Tensor>>;
+    fn forward_data(
+        &self,
+        data: &HeteroData<NodeType, EdgeType>,
+    ) -> Result<HashMap<NodeType, Tensor>> {
+        self.forward_data_t(data, false)
+    }
+    fn forward_data_t(
+        &self,
+        data: &HeteroData<NodeType, EdgeType>,
+        train: bool,
+    ) -> Result<HashMap<NodeType, Tensor>> {
+        self.forward_t(&data.x, &data.edge_index, train)
+    }
 }
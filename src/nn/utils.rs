@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 use std::hash::Hash;
 
-use candle_core::{IndexOp, Result, Tensor, D};
+use candle_core::{DType, Device, IndexOp, Result, Tensor};
 use candle_nn::{Init, Linear, VarBuilder};
 
+use crate::EdgeIndex;
+
 //
 // Linear layer with torch-equivalent initialisation
 //
@@ -24,7 +26,6 @@ pub(crate) fn linear(in_dim: usize, out_dim: usize, vs: VarBuilder) -> Result<Li
     let bs = vs.get_with_hints(out_dim, "bias", init_bs)?;
     Ok(Linear::new(ws, Some(bs)))
 }
-#[allow(dead_code)]
 pub(crate) fn linear_no_bias(in_dim: usize, out_dim: usize, vs: VarBuilder) -> Result<Linear> {
     let bound = 1.0 / (in_dim as f64).sqrt();
     let init_ws = Init::Uniform {
@@ -54,11 +55,8 @@ pub fn out_degree_with_size(edge_index: &Tensor, size: usize) -> Result<Tensor>
         0,
     )
 }
-pub fn out_degree(edge_index: &Tensor) -> Result<Tensor> {
-    out_degree_with_size(
-        edge_index,
-        1 + edge_index.i((0, ..))?.max(D::Minus1)?.to_scalar::<u32>()? as usize,
-    )
+pub fn out_degree(edge_index: &EdgeIndex) -> Result<Tensor> {
+    out_degree_with_size(edge_index.index(), edge_index.num_nodes())
 }
 pub fn in_degree_with_size(edge_index: &Tensor, size: usize) -> Result<Tensor> {
     let dtype = edge_index.dtype();
@@ -69,15 +67,47 @@ pub fn in_degree_with_size(edge_index: &Tensor, size: usize) -> Result<Tensor> {
         0,
     )
 }
-pub fn in_degree(edge_index: &Tensor) -> Result<Tensor> {
-    in_degree_with_size(
-        edge_index,
-        1 + edge_index.i((1, ..))?.max(D::Minus1)?.to_scalar::<u32>()? as usize,
-    )
+pub fn in_degree(edge_index: &EdgeIndex) -> Result<Tensor> {
+    in_degree_with_size(edge_index.index(), edge_index.num_nodes())
+}
+
+pub fn add_self_loops(edge_index: &EdgeIndex) -> Result<EdgeIndex> {
+    let num_nodes = edge_index.num_nodes();
+    let loops = Tensor::arange(0u32, num_nodes as u32, edge_index.device())?;
+    Ok(EdgeIndex::new_unchecked(
+        Tensor::cat(
+            &[edge_index.index(), &Tensor::stack(&[&loops, &loops], 0)?],
+            1,
+        )?,
+        num_nodes,
+        edge_index.num_edges() == 0,
+        edge_index.is_directed(),
+    ))
+}
+/// Returns the edge index without self-loops and the positions of the kept edges.
+pub fn remove_self_loops(edge_index: &EdgeIndex) -> Result<(EdgeIndex, Tensor)> {
+    let rows = edge_index.index().to_vec2::<u32>()?;
+    let kept: Vec<u32> = (0..rows[0].len())
+        .filter(|&e| rows[0][e] != rows[1][e])
+        .map(|e| e as u32)
+        .collect();
+    let kept = Tensor::new(kept.as_slice(), edge_index.device())?;
+    let index = edge_index.index().index_select(&kept, 1)?;
+    Ok((
+        EdgeIndex::new_unchecked(
+            index,
+            edge_index.num_nodes(),
+            edge_index.is_sorted(),
+            edge_index.is_directed(),
+        ),
+        kept,
+    ))
 }
 
 pub fn mean_agg(xs: &Tensor, edge_index: &Tensor, out: &Tensor) -> Result<Tensor> {
-    let out_degree = out_degree(edge_index)?.to_dtype(xs.dtype())?.unsqueeze(1)?;
+    let out_degree = out_degree_with_size(edge_index, out.dim(0)?)?
+        .to_dtype(xs.dtype())?
+        .unsqueeze(1)?;
     out.index_add(
         &edge_index.i((0, ..))?,
         &xs.i(&edge_index.i((1, ..))?)?
@@ -102,3 +132,235 @@ pub fn weighted_sum_agg(
         0,
     )
 }
+
+//
+// Scatter maximum
+//
+//   The position of the maximum of each segment is located on the host and the
+//   values are then collected by `gather`, so the gradient flows back to the
+//   selected entries only (as torch_scatter.scatter_max does). Empty segments
+//   point to an extra zero row and therefore evaluate to zero.
+//
+fn scatter_arg_extremum(
+    src: &Tensor,
+    index: &Tensor,
+    dim_size: usize,
+    is_better: fn(f64, f64) -> bool,
+) -> Result<Tensor> {
+    let num_edges = src.dim(0)?;
+    let num_features = src.dims()[1..].iter().product::<usize>();
+    let values = src
+        .detach()
+        .reshape((num_edges, num_features))?
+        .to_dtype(DType::F64)?
+        .to_device(&Device::Cpu)?
+        .to_vec2::<f64>()?;
+    let index = index.to_device(&Device::Cpu)?.to_vec1::<u32>()?;
+
+    let mut arg = vec![num_edges as u32; dim_size * num_features];
+    for (e, (&i, row)) in index.iter().zip(values.iter()).enumerate() {
+        let offset = i as usize * num_features;
+        for (k, &v) in row.iter().enumerate() {
+            let a = arg[offset + k] as usize;
+            if a == num_edges || is_better(v, values[a][k]) {
+                arg[offset + k] = e as u32;
+            }
+        }
+    }
+    let mut shape = src.dims().to_vec();
+    shape[0] = dim_size;
+    Tensor::from_vec(arg, shape, src.device())
+}
+fn scatter_extremum(
+    src: &Tensor,
+    index: &Tensor,
+    dim_size: usize,
+    is_better: fn(f64, f64) -> bool,
+) -> Result<Tensor> {
+    let arg = scatter_arg_extremum(src, index, dim_size, is_better)?;
+    let mut shape = src.dims().to_vec();
+    shape[0] = 1;
+    let padding = Tensor::zeros(shape, src.dtype(), src.device())?;
+    Tensor::cat(&[src, &padding], 0)?.gather(&arg, 0)
+}
+pub fn scatter_max(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    scatter_extremum(src, index, dim_size, |a, b| a > b)
+}
+pub fn scatter_min(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    scatter_extremum(src, index, dim_size, |a, b| a < b)
+}
+pub fn scatter_sum(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    let mut shape = src.dims().to_vec();
+    shape[0] = dim_size;
+    Tensor::zeros(shape, src.dtype(), src.device())?.index_add(index, src, 0)
+}
+pub fn scatter_mean(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    let count =
+        scatter_sum(&index.ones_like()?.to_dtype(src.dtype())?, index, dim_size)?.maximum(1.0)?;
+    let mut shape = vec![dim_size];
+    shape.resize(src.rank(), 1);
+    scatter_sum(src, index, dim_size)?.broadcast_div(&count.reshape(shape)?)
+}
+pub fn scatter_var(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    let mean = scatter_mean(src, index, dim_size)?;
+    let mean_sqr = scatter_mean(&src.sqr()?, index, dim_size)?;
+    (mean_sqr - mean.sqr()?)?.relu()
+}
+pub fn scatter_std(src: &Tensor, index: &Tensor, dim_size: usize) -> Result<Tensor> {
+    // clamp before sqrt to keep the gradient finite; zero-variance segments give zero
+    let eps = 1e-5;
+    let var = scatter_var(src, index, dim_size)?;
+    var.gt(eps)?
+        .where_cond(&var.maximum(eps)?.sqrt()?, &var.zeros_like()?)
+}
+/// Softmax-weighted sum, sum_j softmax_j(t * x_j) x_j, computed feature-wise.
+/// It interpolates between mean (t = 0) and max (t -> inf).
+pub fn scatter_softmax(src: &Tensor, index: &Tensor, dim_size: usize, t: f64) -> Result<Tensor> {
+    let alpha = segment_softmax(&src.affine(t, 0.0)?, index, dim_size)?;
+    scatter_sum(&src.mul(&alpha)?, index, dim_size)
+}
+/// Power mean, (mean_j x_j^p)^(1/p), with inputs clamped to [0, 100] as in PyG.
+pub fn scatter_powermean(src: &Tensor, index: &Tensor, dim_size: usize, p: f64) -> Result<Tensor> {
+    // clamp before the root to keep the gradient finite; all-zero segments give zero
+    let eps = 1e-12;
+    let mean =
+        scatter_mean(&src.clamp(0.0, 100.0)?.powf(p)?, index, dim_size)?.clamp(0.0, 100.0)?;
+    mean.gt(eps)?
+        .where_cond(&mean.maximum(eps)?.powf(1.0 / p)?, &mean.zeros_like()?)
+}
+
+/// Softmax over the entries of `scores` sharing the same `index`.
+///
+/// The per-segment maximum is subtracted before exponentiation, so large scores
+/// do not overflow. The maximum is detached as softmax is invariant to the shift.
+pub fn segment_softmax(scores: &Tensor, index: &Tensor, num_segments: usize) -> Result<Tensor> {
+    let max = scatter_max(&scores.detach(), index, num_segments)?;
+    let exp = scores.sub(&max.i(index)?)?.exp()?;
+    let mut shape = scores.dims().to_vec();
+    shape[0] = num_segments;
+    let sum = Tensor::zeros(shape, scores.dtype(), scores.device())?.index_add(index, &exp, 0)?;
+    exp.div(&sum.i(index)?)
+}
+
+fn scatter_agg<F>(xs: &Tensor, edge_index: &Tensor, out: &Tensor, f: F) -> Result<Tensor>
+where
+    F: Fn(&Tensor, &Tensor, usize) -> Result<Tensor>,
+{
+    out.add(&f(
+        &xs.i(&edge_index.i((1, ..))?)?,
+        &edge_index.i((0, ..))?,
+        out.dim(0)?,
+    )?)
+}
+pub fn max_agg(xs: &Tensor, edge_index: &Tensor, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, scatter_max)
+}
+pub fn min_agg(xs: &Tensor, edge_index: &Tensor, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, scatter_min)
+}
+pub fn var_agg(xs: &Tensor, edge_index: &Tensor, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, scatter_var)
+}
+pub fn std_agg(xs: &Tensor, edge_index: &Tensor, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, scatter_std)
+}
+pub fn softmax_agg(xs: &Tensor, edge_index: &Tensor, t: f64, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, |src, index, dim_size| {
+        scatter_softmax(src, index, dim_size, t)
+    })
+}
+pub fn powermean_agg(xs: &Tensor, edge_index: &Tensor, p: f64, out: &Tensor) -> Result<Tensor> {
+    scatter_agg(xs, edge_index, out, |src, index, dim_size| {
+        scatter_powermean(src, index, dim_size, p)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Var};
+
+    use super::*;
+
+    fn assert_close(xs: &Tensor, expected: &[[f32; 2]]) -> Result<()> {
+        let xs = xs.to_vec2::<f32>()?;
+        assert_eq!(xs.len(), expected.len());
+        for (x, y) in xs.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((x - y).abs() < 1e-4, "{:?} != {:?}", xs, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregations() -> Result<()> {
+        let device = Device::Cpu;
+        let xs = Tensor::new(&[[1f32, 4.], [3., 2.], [0., 0.]], &device)?;
+        // node 0 <- {1, 2}, node 1 <- {0, 1}, node 2 is isolated
+        let edge_index = Tensor::new(&[[0u32, 0, 1, 1], [1, 2, 0, 1]], &device)?;
+        let out = xs.zeros_like()?;
+
+        assert_close(
+            &max_agg(&xs, &edge_index, &out)?,
+            &[[3., 2.], [3., 4.], [0., 0.]],
+        )?;
+        assert_close(
+            &min_agg(&xs, &edge_index, &out)?,
+            &[[0., 0.], [1., 2.], [0., 0.]],
+        )?;
+        assert_close(
+            &var_agg(&xs, &edge_index, &out)?,
+            &[[2.25, 1.], [1., 1.], [0., 0.]],
+        )?;
+        assert_close(
+            &std_agg(&xs, &edge_index, &out)?,
+            &[[1.5, 1.], [1., 1.], [0., 0.]],
+        )?;
+        assert_close(
+            &softmax_agg(&xs, &edge_index, 0.0, &out)?,
+            &[[1.5, 1.], [2., 3.], [0., 0.]],
+        )?;
+        assert_close(
+            &powermean_agg(&xs, &edge_index, 1.0, &out)?,
+            &[[1.5, 1.], [2., 3.], [0., 0.]],
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_agg_backward() -> Result<()> {
+        let device = Device::Cpu;
+        let xs = Var::new(&[[1f32, 4.], [3., 2.], [0., 0.]], &device)?;
+        let edge_index = Tensor::new(&[[0u32, 0, 1, 1], [1, 2, 0, 1]], &device)?;
+        let out = xs.zeros_like()?;
+        let grads = max_agg(&xs, &edge_index, &out)?.sum_all()?.backward()?;
+        let grad = grads.get(&xs).expect("gradient of xs");
+        assert_close(grad, &[[0., 1.], [2., 1.], [0., 0.]])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_powermean_agg_backward() -> Result<()> {
+        let device = Device::Cpu;
+        // node 0 only receives zeros
+        let xs = Var::new(&[[0f32, 0.], [3., 2.]], &device)?;
+        let edge_index = Tensor::new(&[[0u32, 1, 1], [0, 0, 1]], &device)?;
+        let out = xs.zeros_like()?;
+        let grads = powermean_agg(&xs, &edge_index, 2.0, &out)?
+            .sum_all()?
+            .backward()?;
+        let grad = grads.get(&xs).expect("gradient of xs");
+        for g in grad.flatten_all()?.to_vec1::<f32>()? {
+            assert!(g.is_finite(), "{:?}", grad);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_softmax() -> Result<()> {
+        let device = Device::Cpu;
+        let scores = Tensor::new(&[1000f32, 1000., 0., -1000., 5.], &device)?;
+        let index = Tensor::new(&[0u32, 0, 2, 2, 3], &device)?;
+        let softmax = segment_softmax(&scores, &index, 4)?.to_vec1::<f32>()?;
+        assert_eq!(softmax, [0.5, 0.5, 1.0, 0.0, 1.0]);
+        Ok(())
+    }
+}